@@ -0,0 +1,9 @@
+create table
+    if not exists order_status_history (
+        id bigserial primary key not null,
+        order_id BIGINT not null references orders (id) on delete cascade,
+        from_status varchar not null,
+        to_status varchar not null,
+        actor_id BIGINT not null,
+        changed_at timestamptz not null default now()
+    );
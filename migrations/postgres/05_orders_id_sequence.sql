@@ -0,0 +1,2 @@
+-- order ids used to be supplied by clients, move the sequence past them
+select setval(pg_get_serial_sequence('orders', 'id'), coalesce(max(id), 0) + 1, false) from orders;
@@ -0,0 +1,7 @@
+-- `not valid` keeps the migration from failing on orphans created before the
+-- constraints existed, new and updated rows are checked either way
+alter table orders
+    add constraint orders_pet_id_fkey foreign key (pet_id) references pets (id) not valid;
+
+alter table orders
+    add constraint orders_user_id_fkey foreign key (user_id) references users (id) not valid;
@@ -0,0 +1,2 @@
+-- pets created before sizes existed stay unspecified
+alter table pets add column if not exists size varchar;
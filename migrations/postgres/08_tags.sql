@@ -0,0 +1,39 @@
+create table
+    if not exists tags (
+        id bigserial primary key not null,
+        name varchar not null
+    );
+
+create unique index if not exists tags_name_idx on tags (name);
+
+create table
+    if not exists pet_tags (
+        pet_id bigint not null references pets (id) on delete cascade,
+        tag_id bigint not null references tags (id) on delete cascade,
+        primary key (pet_id, tag_id)
+    );
+
+-- pets carried one JSON encoded tag so far, tags are now matched by name and
+-- the ids clients made up are dropped
+insert into
+    tags (name)
+select distinct
+    p.tags::json ->> 'name'
+from
+    pets p
+where
+    p.tags::json ->> 'name' is not null
+on conflict (name) do nothing;
+
+insert into
+    pet_tags (pet_id, tag_id)
+select
+    p.id,
+    t.id
+from
+    pets p
+    join tags t on t.name = p.tags::json ->> 'name'
+on conflict do nothing;
+
+alter table pets
+drop column tags;
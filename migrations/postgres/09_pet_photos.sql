@@ -0,0 +1,25 @@
+create table
+    if not exists pet_photos (
+        id bigserial primary key not null,
+        pet_id bigint not null references pets (id) on delete cascade,
+        url varchar not null
+    );
+
+create index if not exists pet_photos_pet_id_idx on pet_photos (pet_id);
+
+-- pets carried a single url so far
+insert into
+    pet_photos (pet_id, url)
+select
+    p.id,
+    p.photo_urls
+from
+    pets p
+where
+    p.photo_urls is not null
+    and p.photo_urls <> ''
+order by
+    p.id;
+
+alter table pets
+drop column photo_urls;
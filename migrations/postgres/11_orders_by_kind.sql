@@ -0,0 +1,10 @@
+-- orders placed for a kind of pet have no pet until one is allocated to them
+alter table orders
+alter column pet_id
+drop not null;
+
+alter table orders
+add column pet_category varchar;
+
+alter table orders
+add column pet_size varchar;
@@ -0,0 +1,8 @@
+-- when the pet of the order was reserved, unreserved orders have none;
+-- reservations already held count from now
+alter table orders
+add column reserved_at timestamptz;
+
+update orders
+set reserved_at = now()
+where pet_id is not null and status = 'awaiting';
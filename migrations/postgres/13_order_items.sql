@@ -0,0 +1,43 @@
+create table
+    if not exists order_items (
+        id bigserial primary key not null,
+        order_id bigint not null references orders (id) on delete cascade,
+        pet_id bigint,
+        quantity bigint not null,
+        pet_category varchar,
+        pet_size varchar
+    );
+
+create index if not exists order_items_order_id_idx on order_items (order_id);
+
+create index if not exists order_items_pet_id_idx on order_items (pet_id);
+
+-- orders held a single pet so far, which becomes their only item
+insert into
+    order_items (order_id, pet_id, quantity, pet_category, pet_size)
+select
+    id,
+    pet_id,
+    quantity,
+    pet_category,
+    pet_size
+from
+    orders
+order by
+    id;
+
+-- like in 06_foreign_keys.sql, orphans of old orders are let be
+alter table order_items
+add constraint order_items_pet_id_fkey foreign key (pet_id) references pets (id) not valid;
+
+alter table orders
+drop column pet_id;
+
+alter table orders
+drop column quantity;
+
+alter table orders
+drop column pet_category;
+
+alter table orders
+drop column pet_size;
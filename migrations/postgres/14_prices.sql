@@ -0,0 +1,14 @@
+-- prices are in the minor unit of an ISO 4217 currency, e.g. cents of EUR;
+-- pets created before prices existed have none
+alter table pets
+add column price bigint check (price >= 0),
+add column currency varchar(3);
+
+-- order lines keep the price their pet had when it was reserved for them
+alter table order_items
+add column unit_price bigint,
+add column currency varchar(3);
+
+-- and orders the tax rate, in basis points, in force when they were placed
+alter table orders
+add column tax_rate bigint not null default 0;
@@ -0,0 +1,35 @@
+-- a rule deciding which orders qualify, a discount and when it applies; the
+-- columns of the other rule and discount types stay null
+create table
+    if not exists promotions (
+        id bigserial primary key not null,
+        code varchar,
+        rule varchar not null,
+        category varchar,
+        tag varchar,
+        min_quantity bigint not null default 1,
+        min_subtotal bigint,
+        discount varchar not null,
+        percent bigint,
+        amount bigint,
+        currency varchar(3),
+        starts_at timestamptz,
+        ends_at timestamptz,
+        max_uses bigint,
+        uses bigint not null default 0
+    );
+
+create unique index if not exists promotions_code_idx on promotions (code);
+
+-- the discounts an order got when it was placed, kept when the promotion goes
+create table
+    if not exists order_discounts (
+        id bigserial primary key not null,
+        order_id bigint not null references orders (id) on delete cascade,
+        promotion_id bigint references promotions (id) on delete set null,
+        code varchar,
+        amount bigint not null,
+        free_shipping boolean not null default false
+    );
+
+create index if not exists order_discounts_order_id_idx on order_discounts (order_id);
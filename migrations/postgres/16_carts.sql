@@ -0,0 +1,38 @@
+-- what a customer is about to order, one cart per user; its items may be
+-- there before the cart row is, which only holds the coupon and ship date
+create table
+    if not exists carts (
+        user_id bigint primary key not null references users (id) on delete cascade,
+        coupon varchar,
+        ship_date timestamptz
+    );
+
+-- like order items, a particular pet or a kind of pet; pets deleted meanwhile
+-- drop out of the carts. A particular pet is only ever wanted once and a kind
+-- at most 100 times, see `orders::MAX_QUANTITY`
+create table
+    if not exists cart_items (
+        id bigserial primary key not null,
+        user_id bigint not null references users (id) on delete cascade,
+        pet_id bigint references pets (id) on delete cascade,
+        pet_category varchar,
+        pet_size varchar,
+        quantity bigint not null check (quantity between 1 and 100),
+        check (
+            pet_id is null
+            or quantity = 1
+        )
+    );
+
+create index if not exists cart_items_user_id_idx on cart_items (user_id);
+
+create index if not exists cart_items_pet_id_idx on cart_items (pet_id);
+
+-- a pet or kind of pet takes up one line of a cart, adding it again adds to
+-- the quantity of that line
+create unique index if not exists cart_items_line_idx on cart_items (
+    user_id,
+    coalesce(pet_id, 0),
+    coalesce(pet_category, ''),
+    coalesce(pet_size, '')
+);
@@ -0,0 +1,41 @@
+-- SQLite starts from the schema the Postgres migrations have built up to,
+-- later changes are added to both directories under the same version
+create table
+    if not exists users (
+        id integer primary key autoincrement not null,
+        username text not null,
+        email text not null,
+        password text not null,
+        role text not null default 'customer'
+    );
+
+create unique index if not exists users_username_idx on users (username);
+
+create table
+    if not exists pets (
+        id integer primary key autoincrement not null,
+        category text,
+        photo_urls text,
+        tags text,
+        status text not null
+    );
+
+create table
+    if not exists orders (
+        id integer primary key autoincrement not null,
+        pet_id integer not null references pets (id),
+        user_id integer not null references users (id),
+        quantity integer not null,
+        ship_date datetime,
+        status text not null
+    );
+
+create table
+    if not exists order_status_history (
+        id integer primary key autoincrement not null,
+        order_id integer not null references orders (id) on delete cascade,
+        from_status text not null,
+        to_status text not null,
+        actor_id integer not null,
+        changed_at datetime not null default current_timestamp
+    );
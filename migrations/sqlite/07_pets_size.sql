@@ -0,0 +1,2 @@
+-- pets created before sizes existed stay unspecified
+alter table pets add column size text;
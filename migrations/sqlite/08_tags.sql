@@ -0,0 +1,42 @@
+create table
+    if not exists tags (
+        id integer primary key autoincrement not null,
+        name text not null
+    );
+
+create unique index if not exists tags_name_idx on tags (name);
+
+create table
+    if not exists pet_tags (
+        pet_id integer not null references pets (id) on delete cascade,
+        tag_id integer not null references tags (id) on delete cascade,
+        primary key (pet_id, tag_id)
+    );
+
+-- pets carried one JSON encoded tag so far, tags are now matched by name and
+-- the ids clients made up are dropped
+insert into
+    tags (name)
+select distinct
+    json_extract(p.tags, '$.name')
+from
+    pets p
+where
+    json_extract(p.tags, '$.name') is not null
+on conflict (name) do nothing;
+
+insert into
+    pet_tags (pet_id, tag_id)
+select
+    p.id,
+    t.id
+from
+    pets p
+    join tags t on t.name = json_extract(p.tags, '$.name')
+-- keeps sqlite from reading `on conflict` as part of the join
+where
+    true
+on conflict do nothing;
+
+alter table pets
+drop column tags;
@@ -0,0 +1,9 @@
+-- keyed by the url of the original photo rather than its row, which pet
+-- updates replace, so the thumbnails stay as long as the pet keeps the photo
+create table
+    if not exists photo_thumbnails (
+        photo_url text not null,
+        size integer not null,
+        url text not null,
+        primary key (photo_url, size)
+    );
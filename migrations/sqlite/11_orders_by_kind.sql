@@ -0,0 +1,64 @@
+-- orders placed for a kind of pet have no pet until one is allocated to them.
+-- SQLite cannot drop the `not null` of `pet_id`, so the table is rebuilt.
+-- Dropping `orders` would cascade to its history, which is set aside first:
+-- migrations run in a transaction, where foreign keys cannot be switched off.
+create table orders_backup as
+select
+    *
+from
+    orders;
+
+create table order_status_history_backup as
+select
+    *
+from
+    order_status_history;
+
+drop table order_status_history;
+
+drop table orders;
+
+create table
+    orders (
+        id integer primary key autoincrement not null,
+        pet_id integer references pets (id),
+        user_id integer not null references users (id),
+        quantity integer not null,
+        ship_date datetime,
+        status text not null,
+        pet_category text,
+        pet_size text
+    );
+
+create table
+    order_status_history (
+        id integer primary key autoincrement not null,
+        order_id integer not null references orders (id) on delete cascade,
+        from_status text not null,
+        to_status text not null,
+        actor_id integer not null,
+        changed_at datetime not null default current_timestamp
+    );
+
+insert into
+    orders (id, pet_id, user_id, quantity, ship_date, status)
+select
+    id,
+    pet_id,
+    user_id,
+    quantity,
+    ship_date,
+    status
+from
+    orders_backup;
+
+insert into
+    order_status_history
+select
+    *
+from
+    order_status_history_backup;
+
+drop table order_status_history_backup;
+
+drop table orders_backup;
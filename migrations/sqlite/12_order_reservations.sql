@@ -0,0 +1,7 @@
+-- when the pet of the order was reserved, unreserved orders have none;
+-- reservations already held count from now
+alter table orders add column reserved_at text;
+
+update orders
+set reserved_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')
+where pet_id is not null and status = 'awaiting';
@@ -0,0 +1,86 @@
+-- orders held a single pet so far, which becomes their only item. SQLite
+-- cannot drop `pet_id`, a foreign key, so `orders` is rebuilt like in
+-- 11_orders_by_kind.sql, setting its history aside first.
+create table orders_backup as
+select
+    *
+from
+    orders;
+
+create table order_status_history_backup as
+select
+    *
+from
+    order_status_history;
+
+drop table order_status_history;
+
+drop table orders;
+
+create table
+    orders (
+        id integer primary key autoincrement not null,
+        user_id integer not null references users (id),
+        ship_date datetime,
+        status text not null,
+        reserved_at text
+    );
+
+create table
+    order_status_history (
+        id integer primary key autoincrement not null,
+        order_id integer not null references orders (id) on delete cascade,
+        from_status text not null,
+        to_status text not null,
+        actor_id integer not null,
+        changed_at datetime not null default current_timestamp
+    );
+
+create table
+    order_items (
+        id integer primary key autoincrement not null,
+        order_id integer not null references orders (id) on delete cascade,
+        pet_id integer references pets (id),
+        quantity integer not null,
+        pet_category text,
+        pet_size text
+    );
+
+create index if not exists order_items_order_id_idx on order_items (order_id);
+
+create index if not exists order_items_pet_id_idx on order_items (pet_id);
+
+insert into
+    orders (id, user_id, ship_date, status, reserved_at)
+select
+    id,
+    user_id,
+    ship_date,
+    status,
+    reserved_at
+from
+    orders_backup;
+
+insert into
+    order_status_history
+select
+    *
+from
+    order_status_history_backup;
+
+insert into
+    order_items (order_id, pet_id, quantity, pet_category, pet_size)
+select
+    id,
+    pet_id,
+    quantity,
+    pet_category,
+    pet_size
+from
+    orders_backup
+order by
+    id;
+
+drop table order_status_history_backup;
+
+drop table orders_backup;
@@ -0,0 +1,11 @@
+-- prices are in the minor unit of an ISO 4217 currency, e.g. cents of EUR;
+-- pets created before prices existed have none
+alter table pets add column price integer check (price >= 0);
+alter table pets add column currency text;
+
+-- order lines keep the price their pet had when it was reserved for them
+alter table order_items add column unit_price integer;
+alter table order_items add column currency text;
+
+-- and orders the tax rate, in basis points, in force when they were placed
+alter table orders add column tax_rate integer not null default 0;
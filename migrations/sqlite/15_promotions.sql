@@ -0,0 +1,35 @@
+-- a rule deciding which orders qualify, a discount and when it applies; the
+-- columns of the other rule and discount types stay null
+create table
+    if not exists promotions (
+        id integer primary key autoincrement not null,
+        code text,
+        rule text not null,
+        category text,
+        tag text,
+        min_quantity integer not null default 1,
+        min_subtotal integer,
+        discount text not null,
+        percent integer,
+        amount integer,
+        currency text,
+        starts_at text,
+        ends_at text,
+        max_uses integer,
+        uses integer not null default 0
+    );
+
+create unique index if not exists promotions_code_idx on promotions (code);
+
+-- the discounts an order got when it was placed, kept when the promotion goes
+create table
+    if not exists order_discounts (
+        id integer primary key autoincrement not null,
+        order_id integer not null references orders (id) on delete cascade,
+        promotion_id integer references promotions (id) on delete set null,
+        code text,
+        amount integer not null,
+        free_shipping boolean not null default false
+    );
+
+create index if not exists order_discounts_order_id_idx on order_discounts (order_id);
@@ -0,0 +1,288 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+use anyhow::Result;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, AppState};
+
+#[derive(Serialize, Deserialize, sqlx::Type, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Customer,
+    Staff,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Place orders and manage the ones you placed.
+    PlaceOrders,
+    /// See and change orders placed by anyone.
+    ManageAllOrders,
+    /// Approve and deliver orders.
+    FulfilOrders,
+    EditPets,
+    /// Run promotions and hand out coupon codes.
+    ManagePromotions,
+    ManageUsers,
+}
+
+impl Role {
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Customer => &[PlaceOrders],
+            Role::Staff => &[
+                PlaceOrders,
+                ManageAllOrders,
+                FulfilOrders,
+                EditPets,
+                ManagePromotions,
+            ],
+            Role::Admin => &[
+                PlaceOrders,
+                ManageAllOrders,
+                FulfilOrders,
+                EditPets,
+                ManagePromotions,
+                ManageUsers,
+            ],
+        }
+    }
+
+    pub fn can(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+/// HMAC keys used to sign and check session tokens.
+pub struct TokenKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl: Duration,
+}
+
+impl std::fmt::Debug for TokenKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenKeys")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Claims {
+    sub: u64,
+    username: String,
+    role: Role,
+    exp: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Token {
+    pub token: String,
+    pub token_type: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl TokenKeys {
+    pub fn new(secret: &str, ttl: Duration) -> Self {
+        TokenKeys {
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            ttl,
+        }
+    }
+
+    /// Signs a token for `user_id` that expires after the configured ttl.
+    pub fn issue(&self, user_id: u64, username: &str, role: Role) -> Result<Token> {
+        let expires_at = Utc::now() + self.ttl;
+        let claims = Claims {
+            sub: user_id,
+            username: username.to_string(),
+            role,
+            exp: expires_at.timestamp(),
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)?;
+
+        Ok(Token {
+            token,
+            token_type: "Bearer".to_string(),
+            expires_at,
+        })
+    }
+
+    fn verify(&self, token: &str) -> Result<Claims> {
+        let data = jsonwebtoken::decode::<Claims>(
+            token,
+            &self.decoding,
+            &Validation::new(Algorithm::HS256),
+        )?;
+        Ok(data.claims)
+    }
+}
+
+/// The caller identified by the `Authorization: Bearer <token>` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthUser {
+    pub id: u64,
+    pub username: String,
+    pub role: Role,
+}
+
+impl AuthUser {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.can(permission)
+    }
+
+    /// Like [`AuthUser::can`], but as a ready to return error.
+    pub fn require(&self, permission: Permission) -> Result<(), AppError> {
+        if self.can(permission) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden {
+                role: self.role,
+                required: permission,
+            })
+        }
+    }
+}
+
+/// Verifies the bearer token, if any, and stashes the caller, as currently
+/// stored, in the request extensions for [`AuthUser`] and [`require`].
+/// Requests without a token pass through untouched so public routes keep
+/// working.
+pub(crate) async fn authenticate(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let Some(value) = req.headers().get(header::AUTHORIZATION) else {
+        return next.run(req).await;
+    };
+    let Some(token) = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return AppError::Unauthorized("malformed authorization header").into_response();
+    };
+    let Ok(claims) = state.auth.verify(token) else {
+        return AppError::Unauthorized("invalid or expired token").into_response();
+    };
+    // the role may have changed, or the account be gone, since the token was issued
+    let user = match state.users.get(claims.sub).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return AppError::Unauthorized("the account no longer exists").into_response(),
+        Err(err) => return AppError::Internal(err).into_response(),
+    };
+    req.extensions_mut().insert(AuthUser {
+        id: user.id as u64,
+        username: user.username,
+        role: user.role,
+    });
+    next.run(req).await
+}
+
+type GuardFuture = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+/// Route guard for `middleware::from_fn` rejecting callers whose role lacks
+/// `permission`.
+pub fn require(permission: Permission) -> impl Fn(Request, Next) -> GuardFuture + Clone {
+    move |req: Request, next: Next| -> GuardFuture {
+        Box::pin(async move {
+            match req.extensions().get::<AuthUser>() {
+                Some(user) if user.can(permission) => next.run(req).await,
+                Some(user) => AppError::Forbidden {
+                    role: user.role,
+                    required: permission,
+                }
+                .into_response(),
+                None => AppError::Unauthorized("missing bearer token").into_response(),
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or(AppError::Unauthorized("missing bearer token"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Permission, Role, TokenKeys};
+
+    #[test]
+    fn issue_and_verify() -> anyhow::Result<()> {
+        let keys = TokenKeys::new("secret", Duration::from_secs(60));
+
+        let token = keys.issue(7, "alice", Role::Staff)?;
+        let claims = keys.verify(&token.token)?;
+
+        assert_eq!(7, claims.sub);
+        assert_eq!("alice", claims.username);
+        assert_eq!(Role::Staff, claims.role);
+        assert_eq!("Bearer", token.token_type);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_foreign_and_expired_tokens() -> anyhow::Result<()> {
+        let keys = TokenKeys::new("secret", Duration::from_secs(60));
+        let other = TokenKeys::new("other secret", Duration::from_secs(60));
+        let token = other.issue(7, "alice", Role::Customer)?;
+
+        assert!(keys.verify(&token.token).is_err());
+        assert!(keys.verify("not a token").is_err());
+
+        // jsonwebtoken allows 60 seconds of leeway by default
+        let expired = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &super::Claims {
+                sub: 7,
+                username: "alice".to_string(),
+                role: Role::Customer,
+                exp: chrono::Utc::now().timestamp() - 120,
+            },
+            &keys.encoding,
+        )?;
+        assert!(keys.verify(&expired).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn role_permissions() {
+        assert!(Role::Customer.can(Permission::PlaceOrders));
+        assert!(!Role::Customer.can(Permission::FulfilOrders));
+        assert!(!Role::Customer.can(Permission::EditPets));
+        assert!(Role::Staff.can(Permission::FulfilOrders));
+        assert!(Role::Staff.can(Permission::EditPets));
+        assert!(Role::Staff.can(Permission::ManagePromotions));
+        assert!(!Role::Customer.can(Permission::ManagePromotions));
+        assert!(!Role::Staff.can(Permission::ManageUsers));
+        assert!(Role::Admin.can(Permission::ManageUsers));
+    }
+}
@@ -0,0 +1,955 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::FieldError,
+    orders::{OrderPet, MAX_QUANTITY},
+    validation::{Errors, Validate},
+};
+
+/// What the caller is about to order, kept on the server until it is checked
+/// out into an [`Order`].
+///
+/// [`Order`]: crate::orders::Order
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Cart {
+    /// Changed through `/cart/items`, ignored here.
+    #[serde(default, skip_deserializing)]
+    items: Vec<CartItem>,
+    /// A coupon code to redeem on checkout, matched like the `coupon` of an
+    /// order.
+    #[serde(default)]
+    coupon: Option<String>,
+    #[serde(default)]
+    ship_date: Option<DateTime<Utc>>,
+}
+
+impl Validate for Cart {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(
+                self.coupon
+                    .as_ref()
+                    .is_none_or(|code| !code.trim().is_empty()),
+                "coupon",
+                "must not be empty",
+            )
+            .check(
+                self.ship_date.is_none_or(|date| date >= Utc::now()),
+                "ship_date",
+                "must not be in the past",
+            )
+            .finish()
+    }
+}
+
+/// A particular pet or a kind of pet in a cart, like the items of an order.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CartItem {
+    /// Assigned by the server, whatever the client sends is ignored.
+    #[serde(default)]
+    id: u64,
+    #[serde(default)]
+    pet_id: Option<u64>,
+    /// Only looked at when there is no `pet_id`.
+    #[serde(default)]
+    pet: Option<OrderPet>,
+    /// Always 1 for a particular pet, like on orders.
+    quantity: u64,
+}
+
+impl Validate for CartItem {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(
+                self.pet_id.is_some() || self.pet.is_some(),
+                "pet_id",
+                "either pet_id or pet must be given",
+            )
+            .check(self.quantity >= 1, "quantity", "must be at least 1")
+            .check(
+                self.pet_id.is_none() || self.quantity <= 1,
+                "quantity",
+                "must be 1 for a particular pet",
+            )
+            .check(
+                self.quantity <= MAX_QUANTITY,
+                "quantity",
+                &format!("must be at most {MAX_QUANTITY}"),
+            )
+            .finish()
+    }
+}
+
+/// The new quantity of a cart item.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ItemQuantity {
+    quantity: u64,
+}
+
+impl Validate for ItemQuantity {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(self.quantity >= 1, "quantity", "must be at least 1")
+            .check(
+                self.quantity <= MAX_QUANTITY,
+                "quantity",
+                &format!("must be at most {MAX_QUANTITY}"),
+            )
+            .finish()
+    }
+}
+
+mod service {
+    use axum::{
+        extract::{Path, State},
+        http::{header, StatusCode},
+        response::IntoResponse,
+        Json,
+    };
+    use chrono::Utc;
+
+    use crate::{
+        auth::AuthUser,
+        error::{is_foreign_key_violation, AppError, FieldError},
+        orders::{place_order, Order, MAX_QUANTITY},
+        pet::PetStatus,
+        promotion::normalize_code,
+        validation::Valid,
+        AppState,
+    };
+
+    use super::{
+        storage::{CartDB, CartItemDB},
+        Cart, CartItem, ItemQuantity,
+    };
+
+    fn no_such_pet() -> AppError {
+        AppError::Validation(vec![FieldError {
+            field: "pet_id",
+            message: "does not reference an existing pet".to_string(),
+        }])
+    }
+
+    fn invalid_quantity(message: String) -> AppError {
+        AppError::Validation(vec![FieldError {
+            field: "quantity",
+            message,
+        }])
+    }
+
+    /// The cart of `user_id` as it is now.
+    async fn current(state: &AppState, user_id: u64) -> Result<Json<Cart>, AppError> {
+        Ok(Json(Cart::from(state.carts.get(user_id).await?)))
+    }
+
+    pub async fn get_cart(
+        state: State<AppState>,
+        auth: AuthUser,
+    ) -> Result<impl IntoResponse, AppError> {
+        Ok((StatusCode::OK, current(&state, auth.id).await?))
+    }
+
+    /// Sets the coupon and ship date of the cart. The coupon has to be known,
+    /// whether the order qualifies for it is only seen on checkout.
+    pub async fn update_cart(
+        state: State<AppState>,
+        auth: AuthUser,
+        Valid(cart): Valid<Cart>,
+    ) -> Result<impl IntoResponse, AppError> {
+        if let Some(code) = &cart.coupon {
+            if state
+                .promotions
+                .find_code(&normalize_code(code))
+                .await?
+                .is_none()
+            {
+                return Err(AppError::Validation(vec![FieldError {
+                    field: "coupon",
+                    message: "is not a known coupon code".to_string(),
+                }]));
+            }
+        }
+        state
+            .carts
+            .update(CartDB {
+                user_id: auth.id as i64,
+                coupon: cart.coupon,
+                ship_date: cart.ship_date,
+                items: Vec::new(),
+            })
+            .await?;
+        Ok((StatusCode::OK, current(&state, auth.id).await?))
+    }
+
+    pub async fn clear(
+        state: State<AppState>,
+        auth: AuthUser,
+    ) -> Result<impl IntoResponse, AppError> {
+        state.carts.clear(auth.id).await?;
+        Ok((StatusCode::OK, Json(())))
+    }
+
+    /// Puts a pet, which has to be available, or a kind of pet into the cart.
+    /// A kind that is in there already gets the quantity added instead, a pet
+    /// stays in there once.
+    pub async fn add_item(
+        state: State<AppState>,
+        auth: AuthUser,
+        Valid(item): Valid<CartItem>,
+    ) -> Result<impl IntoResponse, AppError> {
+        if let Some(pet_id) = item.pet_id {
+            let pet = state.pets.get(pet_id).await?.ok_or_else(no_such_pet)?;
+            if pet.status != PetStatus::Available {
+                return Err(AppError::Conflict {
+                    code: "pet_unavailable",
+                    detail: format!("pet {pet_id} is not available"),
+                });
+            }
+        }
+        let added = state
+            .carts
+            .add_item(CartItemDB::from_item(auth.id, item), MAX_QUANTITY)
+            .await
+            .map_err(|err| {
+                // the pet was deleted in the meantime
+                if is_foreign_key_violation(&err) {
+                    no_such_pet()
+                } else {
+                    AppError::Internal(err)
+                }
+            })?;
+        if added.is_none() {
+            return Err(invalid_quantity(format!(
+                "must be at most {MAX_QUANTITY} with what is in the cart already"
+            )));
+        }
+        Ok((StatusCode::OK, current(&state, auth.id).await?))
+    }
+
+    pub async fn update_item(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(item_id): Path<u64>,
+        Valid(item): Valid<ItemQuantity>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let cart = state.carts.get(auth.id).await?;
+        let existing = cart
+            .items
+            .iter()
+            .find(|existing| existing.id == item_id as i64)
+            .ok_or(AppError::NotFound("cart item"))?;
+        if existing.pet_id.is_some() && item.quantity != 1 {
+            return Err(invalid_quantity(
+                "must be 1 for a particular pet".to_string(),
+            ));
+        }
+        if !state
+            .carts
+            .set_quantity(auth.id, item_id, item.quantity)
+            .await?
+        {
+            return Err(AppError::NotFound("cart item"));
+        }
+        Ok((StatusCode::OK, current(&state, auth.id).await?))
+    }
+
+    pub async fn remove_item(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(item_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        if !state.carts.remove_item(auth.id, item_id).await? {
+            return Err(AppError::NotFound("cart item"));
+        }
+        Ok((StatusCode::OK, current(&state, auth.id).await?))
+    }
+
+    /// Places an order of what is in the cart and empties it, in one
+    /// transaction. The pets are checked for availability again and reserved
+    /// like for any other order, see [`place_order`]. When the order cannot
+    /// be placed the cart stays as it is.
+    pub async fn checkout(
+        state: State<AppState>,
+        auth: AuthUser,
+    ) -> Result<impl IntoResponse, AppError> {
+        let uow = state.begin().await?;
+        let cart = uow.carts.get(auth.id).await?;
+        if cart.ship_date.is_some_and(|date| date < Utc::now()) {
+            return Err(AppError::Validation(vec![FieldError {
+                field: "ship_date",
+                message: "must not be in the past".to_string(),
+            }]));
+        }
+        // someone checking out the same cart at the same time empties it
+        // first, after which this one finds nothing to clear
+        if cart.items.is_empty() || !uow.carts.clear(auth.id).await? {
+            return Err(AppError::Conflict {
+                code: "cart_empty",
+                detail: "there is nothing in the cart to order".to_string(),
+            });
+        }
+        let order = place_order(
+            &uow,
+            auth.id,
+            state.tax_rate_bps,
+            cart.items.into_iter().map(Into::into).collect(),
+            cart.ship_date,
+            cart.coupon.as_deref(),
+        )
+        .await?;
+        uow.commit().await?;
+        Ok((
+            StatusCode::CREATED,
+            [(header::LOCATION, format!("/orders/{}", order.id))],
+            Json(Order::from(order)),
+        ))
+    }
+}
+
+pub(crate) mod storage {
+    use std::fmt::Debug;
+
+    use anyhow::Result;
+    use axum::async_trait;
+    use chrono::{DateTime, Utc};
+    use sqlx::{Connection, FromRow};
+
+    use crate::{
+        orders::{storage::OrderItemDB, OrderPet},
+        persistence::{foreign_key_violation, on_conn, MemoryDb, Sql},
+        pet::{PetCategory, PetSize},
+    };
+
+    use super::{Cart, CartItem};
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct CartDB {
+        pub user_id: i64,
+        pub coupon: Option<String>,
+        pub ship_date: Option<DateTime<Utc>>,
+        /// Kept in `cart_items`, ordered by id.
+        #[sqlx(skip)]
+        pub items: Vec<CartItemDB>,
+    }
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct CartItemDB {
+        pub id: i64,
+        pub user_id: i64,
+        pub pet_id: Option<i64>,
+        /// The kind of pet, see [`OrderPet`], only set without a `pet_id`.
+        pub pet_category: Option<PetCategory>,
+        pub pet_size: Option<PetSize>,
+        pub quantity: i64,
+    }
+
+    impl CartItemDB {
+        pub(super) fn from_item(user_id: u64, item: CartItem) -> CartItemDB {
+            let kind = item.pet.filter(|_| item.pet_id.is_none());
+            CartItemDB {
+                id: 0,
+                user_id: user_id as i64,
+                pet_id: item.pet_id.map(|pet_id| pet_id as i64),
+                pet_category: kind.as_ref().map(|kind| kind.category.clone()),
+                pet_size: kind.map(|kind| kind.pet_size),
+                quantity: item.quantity as i64,
+            }
+        }
+
+        pub(super) fn pet(&self) -> Option<OrderPet> {
+            Some(OrderPet {
+                category: self.pet_category.clone()?,
+                pet_size: self.pet_size.clone()?,
+            })
+        }
+    }
+
+    impl From<CartItemDB> for CartItem {
+        fn from(item: CartItemDB) -> Self {
+            CartItem {
+                pet: item.pet(),
+                id: item.id as u64,
+                pet_id: item.pet_id.map(|pet_id| pet_id as u64),
+                quantity: item.quantity as u64,
+            }
+        }
+    }
+
+    /// An item of the order placed on checkout.
+    impl From<CartItemDB> for OrderItemDB {
+        fn from(item: CartItemDB) -> Self {
+            OrderItemDB {
+                id: 0,
+                order_id: 0,
+                pet_id: item.pet_id,
+                quantity: item.quantity,
+                pet_category: item.pet_category,
+                pet_size: item.pet_size,
+                unit_price: None,
+                currency: None,
+            }
+        }
+    }
+
+    impl From<CartDB> for Cart {
+        fn from(cart: CartDB) -> Self {
+            Cart {
+                items: cart.items.into_iter().map(CartItem::from).collect(),
+                coupon: cart.coupon,
+                ship_date: cart.ship_date,
+            }
+        }
+    }
+
+    #[async_trait]
+    pub trait CartRepository: Debug + Send + Sync {
+        /// The cart of `user_id`, empty when they have none.
+        async fn get(&self, user_id: u64) -> Result<CartDB>;
+        /// Stores the coupon and ship date of the cart, leaving its items alone.
+        async fn update(&self, cart: CartDB) -> Result<()>;
+        /// Returns the id of the new item. A kind of pet already in the cart
+        /// gets the quantity added instead, a pet already in it is left as it
+        /// is, returning the id of that item. Returns `None` without changing
+        /// anything when the quantity would exceed `max_quantity`.
+        async fn add_item(&self, item: CartItemDB, max_quantity: u64) -> Result<Option<i64>>;
+        /// Returns `false` when `user_id` has no item `item_id` in their cart.
+        async fn set_quantity(&self, user_id: u64, item_id: u64, quantity: u64) -> Result<bool>;
+        /// Returns `false` when `user_id` has no item `item_id` in their cart.
+        async fn remove_item(&self, user_id: u64, item_id: u64) -> Result<bool>;
+        /// Removes the items, coupon and ship date. Returns `false` when there
+        /// were no items.
+        async fn clear(&self, user_id: u64) -> Result<bool>;
+    }
+
+    #[async_trait]
+    impl CartRepository for Sql {
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, user_id: u64) -> Result<CartDB> {
+            let (cart, items): (Option<CartDB>, Vec<CartItemDB>) = on_conn!(self, |conn| {
+                (
+                    sqlx::query_as("select * from carts c where c.user_id = $1")
+                        .bind(user_id as i64)
+                        .fetch_optional(&mut *conn)
+                        .await?,
+                    sqlx::query_as("select * from cart_items i where i.user_id = $1 order by i.id")
+                        .bind(user_id as i64)
+                        .fetch_all(&mut *conn)
+                        .await?,
+                )
+            });
+
+            Ok(CartDB {
+                items,
+                ..cart.unwrap_or(CartDB {
+                    user_id: user_id as i64,
+                    coupon: None,
+                    ship_date: None,
+                    items: Vec::new(),
+                })
+            })
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update(&self, cart: CartDB) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query(
+                    "insert into carts (user_id, coupon, ship_date) values ($1, $2, $3)
+                    on conflict (user_id)
+                    do update set coupon = excluded.coupon, ship_date = excluded.ship_date;",
+                )
+                .bind(cart.user_id)
+                .bind(cart.coupon)
+                .bind(cart.ship_date)
+                .execute(&mut *conn)
+                .await?;
+            });
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn add_item(&self, item: CartItemDB, max_quantity: u64) -> Result<Option<i64>> {
+            // the conflict target is `cart_items_line_idx`
+            let res: Option<(i64,)> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "insert into cart_items (user_id, pet_id, pet_category, pet_size, quantity)
+                    values ($1, $2, $3, $4, $5)
+                    on conflict (
+                        user_id,
+                        coalesce(pet_id, 0),
+                        coalesce(pet_category, ''),
+                        coalesce(pet_size, '')
+                    )
+                    do update set quantity = case
+                        when cart_items.pet_id is null
+                        then cart_items.quantity + excluded.quantity
+                        else cart_items.quantity
+                    end
+                    where cart_items.pet_id is not null
+                        or cart_items.quantity + excluded.quantity <= $6
+                    returning id;",
+                )
+                .bind(item.user_id)
+                .bind(item.pet_id)
+                .bind(item.pet_category)
+                .bind(item.pet_size)
+                .bind(item.quantity)
+                .bind(i64::try_from(max_quantity).unwrap_or(i64::MAX))
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+
+            Ok(res.map(|(id,)| id))
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn set_quantity(&self, user_id: u64, item_id: u64, quantity: u64) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query("update cart_items set quantity = $3 where id = $2 and user_id = $1;")
+                    .bind(user_id as i64)
+                    .bind(item_id as i64)
+                    .bind(i64::try_from(quantity).unwrap_or(i64::MAX))
+                    .execute(&mut *conn)
+                    .await
+                    .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn remove_item(&self, user_id: u64, item_id: u64) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query("delete from cart_items where id = $2 and user_id = $1;")
+                    .bind(user_id as i64)
+                    .bind(item_id as i64)
+                    .execute(&mut *conn)
+                    .await
+                    .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn clear(&self, user_id: u64) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                let mut tx = conn.begin().await?;
+                let rows_affected = sqlx::query("delete from cart_items where user_id = $1;")
+                    .bind(user_id as i64)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                sqlx::query("delete from carts where user_id = $1;")
+                    .bind(user_id as i64)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+                rows_affected
+            });
+
+            Ok(rows_affected > 0)
+        }
+    }
+
+    #[async_trait]
+    impl CartRepository for MemoryDb {
+        async fn get(&self, user_id: u64) -> Result<CartDB> {
+            let tables = self.lock();
+            let user_id = user_id as i64;
+            let cart = tables.carts.get(&user_id).cloned().unwrap_or(CartDB {
+                user_id,
+                coupon: None,
+                ship_date: None,
+                items: Vec::new(),
+            });
+            Ok(CartDB {
+                items: tables
+                    .cart_items
+                    .values()
+                    .filter(|item| item.user_id == user_id)
+                    .cloned()
+                    .collect(),
+                ..cart
+            })
+        }
+
+        async fn update(&self, cart: CartDB) -> Result<()> {
+            let mut tables = self.lock();
+            if !tables.users.contains_key(&cart.user_id) {
+                return Err(foreign_key_violation("carts_user_id_fkey"));
+            }
+            tables.carts.insert(
+                cart.user_id,
+                CartDB {
+                    items: Vec::new(),
+                    ..cart
+                },
+            );
+            Ok(())
+        }
+
+        async fn add_item(&self, item: CartItemDB, max_quantity: u64) -> Result<Option<i64>> {
+            let mut tables = self.lock();
+            let line = tables.cart_items.values_mut().find(|line| {
+                line.user_id == item.user_id
+                    && line.pet_id == item.pet_id
+                    && line.pet_category == item.pet_category
+                    && line.pet_size == item.pet_size
+            });
+            if let Some(line) = line {
+                if line.pet_id.is_none() {
+                    let quantity = line.quantity.saturating_add(item.quantity);
+                    if quantity as u64 > max_quantity {
+                        return Ok(None);
+                    }
+                    line.quantity = quantity;
+                }
+                return Ok(Some(line.id));
+            }
+            if !tables.users.contains_key(&item.user_id) {
+                return Err(foreign_key_violation("cart_items_user_id_fkey"));
+            }
+            if item
+                .pet_id
+                .is_some_and(|pet_id| !tables.pets.contains_key(&pet_id))
+            {
+                return Err(foreign_key_violation("cart_items_pet_id_fkey"));
+            }
+            let id = tables.next_id();
+            tables.cart_items.insert(id, CartItemDB { id, ..item });
+            Ok(Some(id))
+        }
+
+        async fn set_quantity(&self, user_id: u64, item_id: u64, quantity: u64) -> Result<bool> {
+            let mut tables = self.lock();
+            let item = tables
+                .cart_items
+                .get_mut(&(item_id as i64))
+                .filter(|item| item.user_id == user_id as i64);
+            Ok(item
+                .map(|item| item.quantity = i64::try_from(quantity).unwrap_or(i64::MAX))
+                .is_some())
+        }
+
+        async fn remove_item(&self, user_id: u64, item_id: u64) -> Result<bool> {
+            let mut tables = self.lock();
+            let item_id = item_id as i64;
+            if tables
+                .cart_items
+                .get(&item_id)
+                .is_none_or(|item| item.user_id != user_id as i64)
+            {
+                return Ok(false);
+            }
+            tables.cart_items.remove(&item_id);
+            Ok(true)
+        }
+
+        async fn clear(&self, user_id: u64) -> Result<bool> {
+            let mut tables = self.lock();
+            let user_id = user_id as i64;
+            tables.carts.remove(&user_id);
+            let before = tables.cart_items.len();
+            tables.cart_items.retain(|_, item| item.user_id != user_id);
+            Ok(tables.cart_items.len() < before)
+        }
+    }
+}
+
+pub mod api {
+    use axum::{
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+
+    use crate::{
+        auth::{self, Permission},
+        AppState,
+    };
+
+    use super::service;
+
+    pub(crate) fn create_router() -> Router<AppState> {
+        Router::new()
+            .route(
+                "/",
+                get(service::get_cart)
+                    .post(service::update_cart)
+                    .delete(service::clear),
+            )
+            .route("/items", post(service::add_item))
+            .route(
+                "/items/:item_id",
+                post(service::update_item).delete(service::remove_item),
+            )
+            .route("/checkout", post(service::checkout))
+            .route_layer(middleware::from_fn(auth::require(Permission::PlaceOrders)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{
+        pet::{storage::PetDB, PetCategory, PetSize, PetStatus},
+        testing, AppState,
+    };
+
+    /// Inserts a customer and an available Rodent priced 1500 EUR, returns
+    /// their ids.
+    async fn seed(state: &AppState) -> Result<(i64, i64)> {
+        let hamster = PetDB {
+            id: 0,
+            category: Some(PetCategory::Rodents),
+            status: PetStatus::Available,
+            size: Some(PetSize::Terraium),
+            price: Some(1500),
+            currency: Some("EUR".to_string()),
+            photo_urls: Vec::new(),
+            tags: Vec::new(),
+            thumbnails: Vec::new(),
+        };
+        testing::seed(state, hamster).await
+    }
+
+    mod api {
+        use axum::http::StatusCode;
+        use serde_json::json;
+
+        use crate::{
+            cart::{api, tests::seed},
+            testing::{app, request, send},
+            AppState,
+        };
+
+        #[tokio::test]
+        async fn require_permissions() -> anyhow::Result<()> {
+            let state = AppState::in_memory();
+            let (customer_id, pet_id) = seed(&state).await?;
+            let cart = || app(&state, "/cart", api::create_router());
+            let item = json!({"pet_id": pet_id, "quantity": 1});
+
+            let anonymous = request(&state, "POST", "/cart/items", None, Some(item.clone()))?;
+            assert_eq!(
+                StatusCode::UNAUTHORIZED,
+                send(cart(), anonymous).await?.status()
+            );
+            let added = request(&state, "POST", "/cart/items", Some(customer_id), Some(item))?;
+            assert!(send(cart(), added).await?.status().is_success());
+            let own = request(&state, "GET", "/cart", Some(customer_id), None)?;
+            assert_eq!(StatusCode::OK, send(cart(), own).await?.status());
+            Ok(())
+        }
+    }
+
+    mod service {
+        use axum::{
+            body::to_bytes,
+            extract::{Path, State},
+            http::StatusCode,
+            response::{IntoResponse, Response},
+        };
+        use serde_json::{json, Value};
+
+        use crate::{
+            cart::{service, tests::seed},
+            pet::PetStatus,
+            testing::customer,
+            validation::Valid,
+            AppState,
+        };
+
+        async fn body(res: Response) -> anyhow::Result<Value> {
+            Ok(serde_json::from_slice(
+                &to_bytes(res.into_body(), usize::MAX).await?,
+            )?)
+        }
+
+        #[tokio::test]
+        async fn checkout_cart() -> anyhow::Result<()> {
+            let state = State(AppState::in_memory());
+            let (user_id, pet_id) = seed(&state).await?;
+            let auth = || customer(user_id);
+            let add = |item: Value| {
+                service::add_item(
+                    state.clone(),
+                    auth(),
+                    Valid(serde_json::from_value(item).unwrap()),
+                )
+            };
+
+            add(json!({"pet_id": pet_id, "quantity": 1}))
+                .await
+                .into_response();
+            let kind = json!({"category": "Birds", "pet_size": "House"});
+            add(json!({"pet": kind, "quantity": 1}))
+                .await
+                .into_response();
+            let res = add(json!({"pet": kind, "quantity": 2}))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::OK, res.status());
+            let cart = body(res).await?;
+            assert_eq!(2, cart["items"].as_array().unwrap().len());
+            assert_eq!(json!(3), cart["items"][1]["quantity"], "added up");
+
+            // a pet is in there once, kinds only so many times
+            let res = add(json!({"pet_id": pet_id, "quantity": 1}))
+                .await
+                .into_response();
+            assert_eq!(json!(1), body(res).await?["items"][0]["quantity"]);
+            let res = add(json!({"pet": kind, "quantity": 98}))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let pet_item = cart["items"][0]["id"].as_u64().unwrap();
+            let two = Valid(serde_json::from_value(json!({"quantity": 2}))?);
+            let res = service::update_item(state.clone(), auth(), Path(pet_item), two)
+                .await
+                .into_response();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+            let birds = cart["items"][1]["id"].as_u64().unwrap();
+            let res = service::remove_item(state.clone(), auth(), Path(birds))
+                .await
+                .into_response();
+            assert_eq!(1, body(res).await?["items"].as_array().unwrap().len());
+            let res = service::remove_item(state.clone(), customer(user_id + 1), Path(birds))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+            let details =
+                |coupon: &str| Valid(serde_json::from_value(json!({"coupon": coupon})).unwrap());
+            let res = service::update_cart(state.clone(), auth(), details("SPRING"))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+            // taken by someone else in the meantime, the cart stays
+            state
+                .pets
+                .update_status(pet_id as u64, PetStatus::Available, PetStatus::Pending)
+                .await?;
+            let res = service::checkout(state.clone(), auth())
+                .await
+                .into_response();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+            assert_eq!(1, state.carts.get(user_id as u64).await?.items.len());
+
+            state
+                .pets
+                .update_status(pet_id as u64, PetStatus::Pending, PetStatus::Available)
+                .await?;
+            let res = service::checkout(state.clone(), auth())
+                .await
+                .into_response();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let order = body(res).await?;
+            assert_eq!(json!(pet_id), order["pet_id"]);
+            assert_eq!(json!("awaiting"), order["status"]);
+            assert_eq!(json!(1500), order["total"]);
+            assert_eq!(
+                PetStatus::Pending,
+                state.pets.get(pet_id as u64).await?.unwrap().status
+            );
+            assert!(state.carts.get(user_id as u64).await?.items.is_empty());
+
+            let res = service::checkout(state.clone(), auth())
+                .await
+                .into_response();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+            Ok(())
+        }
+    }
+
+    mod storage {
+        use chrono::{TimeZone, Utc};
+
+        use crate::{
+            cart::{
+                storage::{CartDB, CartItemDB},
+                tests::seed,
+            },
+            pet::{PetCategory, PetSize},
+            AppState,
+        };
+
+        #[tokio::test]
+        async fn cart_crud() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, pet_id) = seed(&state).await?;
+                let item = |pet_id: Option<i64>| CartItemDB {
+                    id: 0,
+                    user_id,
+                    pet_id,
+                    pet_category: pet_id.is_none().then_some(PetCategory::Birds),
+                    pet_size: pet_id.is_none().then_some(PetSize::House),
+                    quantity: 1,
+                };
+                let add = |item: CartItemDB| state.carts.add_item(item, 5);
+                let pet_item = add(item(Some(pet_id))).await?.unwrap();
+                let kind_item = add(item(None)).await?.unwrap();
+                assert!(add(item(Some(pet_id + 100))).await.is_err());
+                // adding the same line again adds to it, up to the limit
+                assert_eq!(Some(pet_item), add(item(Some(pet_id))).await?);
+                let three = CartItemDB {
+                    quantity: 3,
+                    ..item(None)
+                };
+                assert_eq!(Some(kind_item), add(three.clone()).await?);
+                assert_eq!(None, add(three).await?);
+                assert_eq!(4, state.carts.get(user_id as u64).await?.items[1].quantity);
+                let ship_date = Utc.with_ymd_and_hms(2030, 5, 1, 9, 0, 0).unwrap();
+                let details = CartDB {
+                    user_id,
+                    coupon: Some("SPRING".to_string()),
+                    ship_date: Some(ship_date),
+                    items: Vec::new(),
+                };
+                state.carts.update(details.clone()).await?;
+                state.carts.update(details.clone()).await?;
+
+                assert!(
+                    state
+                        .carts
+                        .set_quantity(user_id as u64, kind_item as u64, 4)
+                        .await?
+                );
+                assert!(!state.carts.set_quantity(0, kind_item as u64, 4).await?);
+                let cart = state.carts.get(user_id as u64).await?;
+                assert_eq!(
+                    CartDB {
+                        items: vec![
+                            CartItemDB {
+                                id: pet_item,
+                                ..item(Some(pet_id))
+                            },
+                            CartItemDB {
+                                id: kind_item,
+                                quantity: 4,
+                                ..item(None)
+                            },
+                        ],
+                        ..details
+                    },
+                    cart
+                );
+
+                // deleted pets drop out of the cart
+                state.pets.delete(pet_id as u64).await?;
+                assert_eq!(1, state.carts.get(user_id as u64).await?.items.len());
+                assert!(!state.carts.remove_item(0, kind_item as u64).await?);
+                assert!(state.carts.clear(user_id as u64).await?);
+                assert!(!state.carts.clear(user_id as u64).await?);
+                let cart = state.carts.get(user_id as u64).await?;
+                assert_eq!((None, Vec::new()), (cart.coupon, cart.items));
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -1,17 +1,139 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::Serialize;
+use serde_json::{json, Map, Value};
 
-pub struct AppError(pub anyhow::Error);
+use crate::auth::{Permission, Role};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Every failure a handler can report. Rendered as an RFC 7807
+/// `application/problem+json` body carrying a stable `code`.
+#[derive(Debug)]
+pub enum AppError {
+    /// The named resource does not exist.
+    NotFound(&'static str),
+    Conflict {
+        code: &'static str,
+        detail: String,
+    },
+    Validation(Vec<FieldError>),
+    /// An upload larger than `limit` bytes.
+    PayloadTooLarge {
+        limit: usize,
+    },
+    /// The request body is in a format that is not accepted, e.g. an upload
+    /// that is not an image.
+    UnsupportedMediaType(&'static str),
+    Unauthorized(&'static str),
+    Forbidden {
+        role: Role,
+        required: Permission,
+    },
+    /// Anything unexpected. The cause is logged, never returned to the client.
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict { code, .. } => code,
+            AppError::Validation(_) => "validation_failed",
+            AppError::PayloadTooLarge { .. } => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden { .. } => "insufficient_role",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            AppError::NotFound(resource) => format!("{resource} not found"),
+            AppError::Conflict { detail, .. } => detail.clone(),
+            AppError::Validation(_) => "the request is invalid".to_string(),
+            AppError::PayloadTooLarge { limit } => format!("uploads are limited to {limit} bytes"),
+            AppError::UnsupportedMediaType(detail) => detail.to_string(),
+            AppError::Unauthorized(reason) => reason.to_string(),
+            AppError::Forbidden { .. } => "your role does not allow this".to_string(),
+            AppError::Internal(_) => "something went wrong on our side".to_string(),
+        }
+    }
+
+    /// Problem members beyond the standard ones.
+    fn extensions(&self) -> Map<String, Value> {
+        let mut extensions = Map::new();
+        match self {
+            AppError::Validation(errors) => {
+                extensions.insert("errors".to_string(), json!(errors));
+            }
+            AppError::PayloadTooLarge { limit } => {
+                extensions.insert("limit_bytes".to_string(), json!(limit));
+            }
+            AppError::Forbidden { role, required } => {
+                extensions.insert("role".to_string(), json!(role));
+                extensions.insert("required_permission".to_string(), json!(required));
+            }
+            _ => {}
+        }
+        extensions
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        if let AppError::Internal(err) = &self {
+            tracing::error!("internal error: {err:?}");
+        }
+
+        let status = self.status();
+        let mut body = Map::new();
+        body.insert(
+            "type".to_string(),
+            json!(format!("/problems/{}", self.code())),
+        );
+        body.insert(
+            "title".to_string(),
+            json!(status.canonical_reason().unwrap_or_default()),
+        );
+        body.insert("status".to_string(), json!(status.as_u16()));
+        body.insert("detail".to_string(), json!(self.detail()));
+        body.insert("code".to_string(), json!(self.code()));
+        body.extend(self.extensions());
+
+        let mut response = (status, Json(Value::Object(body))).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/problem+json"),
+        );
+        if let AppError::Unauthorized(_) = self {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+        }
+        response
     }
 }
 
@@ -20,6 +142,78 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::Internal(err.into())
+    }
+}
+
+/// Whether `err` was caused by a unique constraint violation in the database.
+pub fn is_unique_violation(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Database(db_err)) if db_err.is_unique_violation()
+    )
+}
+
+/// Whether `err` was caused by a foreign key violation in the database.
+pub fn is_foreign_key_violation(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Database(db_err)) if db_err.is_foreign_key_violation()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, http::StatusCode, response::IntoResponse};
+    use serde_json::Value;
+
+    use crate::auth::{Permission, Role};
+
+    use super::{AppError, FieldError};
+
+    async fn render(err: AppError) -> anyhow::Result<(StatusCode, String, Value)> {
+        let response = err.into_response();
+        let status = response.status();
+        let content_type = response.headers()["content-type"].to_str()?.to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, content_type, serde_json::from_slice(&body)?))
+    }
+
+    #[tokio::test]
+    async fn internal_details_are_not_leaked() -> anyhow::Result<()> {
+        let (status, content_type, body) =
+            render(anyhow::anyhow!("relation \"orders\" does not exist").into()).await?;
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!("application/problem+json", content_type);
+        assert_eq!("internal_error", body["code"]);
+        assert_eq!(500, body["status"]);
+        assert!(!body.to_string().contains("relation"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn problem_extensions() -> anyhow::Result<()> {
+        let (status, _, body) = render(AppError::Validation(vec![FieldError {
+            field: "quantity",
+            message: "must be at least 1".to_string(),
+        }]))
+        .await?;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert_eq!("quantity", body["errors"][0]["field"]);
+
+        let (status, _, body) = render(AppError::PayloadTooLarge { limit: 1024 }).await?;
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, status);
+        assert_eq!(1024, body["limit_bytes"]);
+
+        let (status, _, body) = render(AppError::Forbidden {
+            role: Role::Customer,
+            required: Permission::EditPets,
+        })
+        .await?;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+        assert_eq!("customer", body["role"]);
+        assert_eq!("edit_pets", body["required_permission"]);
+        Ok(())
     }
 }
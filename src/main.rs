@@ -3,31 +3,62 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use anyhow::Result;
+use auth::TokenKeys;
 use axum::extract::State;
 use axum::http::StatusCode;
+use axum::middleware;
 use axum::routing::get;
 use axum::Json;
 use axum::Router;
+use cart::storage::CartRepository;
 use config::AppConfig;
-use orders::Order;
-use persistence::ArcPgPool;
+use media::Media;
+use orders::storage::OrderRepository;
+use persistence::DbPool;
+use persistence::MemoryDb;
+use persistence::Sql;
 use persistence::StorageConfig;
-use persistence::StorageIml;
+use persistence::Transactional;
+use persistence::UnitOfWork;
+use persistence::MEMORY_URL;
+use pet::storage::PetRepository;
+use promotion::storage::PromotionRepository;
+use tag::storage::TagRepository;
 
+pub mod auth;
+pub mod cart;
 pub mod config;
 pub mod error;
+pub mod media;
 pub mod orders;
+pub mod pagination;
 pub mod persistence;
 pub mod pet;
+pub mod promotion;
+pub mod store;
+pub mod tag;
+#[cfg(test)]
+mod testing;
 pub mod user;
-use persistence::Storage;
+pub mod validation;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tracing::info;
+use user::storage::UserRepository;
 
 #[derive(Debug)]
 pub struct AppStateInner {
-    pub db: ArcPgPool,
+    pub db: Arc<dyn Transactional>,
+    pub carts: Arc<dyn CartRepository>,
+    pub orders: Arc<dyn OrderRepository>,
+    pub pets: Arc<dyn PetRepository>,
+    pub promotions: Arc<dyn PromotionRepository>,
+    pub tags: Arc<dyn TagRepository>,
+    pub users: Arc<dyn UserRepository>,
+    pub auth: TokenKeys,
+    pub media: Media,
+    /// Of new orders, see [`AppConfig::tax_rate_bps`].
+    pub tax_rate_bps: u32,
     pub version: String,
 }
 
@@ -37,12 +68,100 @@ struct AppState {
 }
 
 impl AppState {
+    /// Connects to and migrates the database described by `config`.
+    pub async fn new(config: &AppConfig) -> Result<AppState> {
+        let auth = TokenKeys::new(config.token_secret(), config.token_ttl());
+        let media = Media::from(config);
+        if config.db() == MEMORY_URL {
+            return Ok(AppState::with_repositories(
+                MemoryDb::default(),
+                auth,
+                media,
+                config.tax_rate_bps(),
+            ));
+        }
+        let pool = DbPool::connect(StorageConfig::from(config)).await?;
+
+        Ok(AppState::with_repositories(
+            Sql::Pool(pool),
+            auth,
+            media,
+            config.tax_rate_bps(),
+        ))
+    }
+
+    fn with_repositories<R>(
+        repositories: R,
+        auth: TokenKeys,
+        media: Media,
+        tax_rate_bps: u32,
+    ) -> AppState
+    where
+        R: CartRepository
+            + OrderRepository
+            + PetRepository
+            + PromotionRepository
+            + TagRepository
+            + UserRepository
+            + Transactional
+            + Clone
+            + 'static,
+    {
+        AppState {
+            inner: Arc::new(AppStateInner {
+                db: Arc::new(repositories.clone()),
+                carts: Arc::new(repositories.clone()),
+                orders: Arc::new(repositories.clone()),
+                pets: Arc::new(repositories.clone()),
+                promotions: Arc::new(repositories.clone()),
+                tags: Arc::new(repositories.clone()),
+                users: Arc::new(repositories),
+                auth,
+                media,
+                tax_rate_bps,
+                version: "0.0.1".to_string(),
+            }),
+        }
+    }
+
+    /// Starts a transaction spanning the repositories of the returned
+    /// [`UnitOfWork`].
+    pub async fn begin(&self) -> Result<UnitOfWork> {
+        self.db.begin().await
+    }
+
     pub async fn shutdown(self) -> Result<()> {
         self.inner.db.close().await;
         Ok(())
     }
 }
 
+#[cfg(test)]
+impl AppState {
+    /// State on a fresh [`MemoryDb`], no database needed.
+    pub fn in_memory() -> AppState {
+        let auth = TokenKeys::new("test-token-secret", std::time::Duration::from_secs(60));
+        AppState::with_repositories(MemoryDb::default(), auth, Media::temporary(), 0)
+    }
+
+    /// A fresh [`MemoryDb`] and in-memory SQLite state, for storage tests that
+    /// have to hold on every backend.
+    pub async fn test_backends() -> Result<Vec<AppState>> {
+        let sqlite = DbPool::connect(StorageConfig {
+            db_path: "sqlite::memory:".to_string(),
+            max_connections: 1,
+            acquire_timeout: std::time::Duration::from_secs(1),
+        })
+        .await?;
+        let auth = TokenKeys::new("test-token-secret", std::time::Duration::from_secs(60));
+
+        Ok(vec![
+            AppState::in_memory(),
+            AppState::with_repositories(Sql::Pool(sqlite), auth, Media::temporary(), 0),
+        ])
+    }
+}
+
 impl Deref for AppState {
     type Target = Arc<AppStateInner>;
 
@@ -58,23 +177,21 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     info!("Loading config");
-    let app_config = AppConfig::load_config()?;
-    let storage_config = StorageConfig {
-        db_path: app_config.db(),
+    let app_config = match profile_arg() {
+        Some(profile) => AppConfig::load_profile(&profile)?,
+        None => AppConfig::load_config()?,
     };
     info!("Connecting to DB");
-    let pool = StorageIml.conn(storage_config).await?;
-    StorageIml.migrate(pool.clone()).await?;
-
-    let state = AppState {
-        inner: Arc::new(AppStateInner {
-            db: pool,
-            version: "0.0.1".to_string(),
-        }),
-    };
+    let state = AppState::new(&app_config).await?;
 
     info!("Connected to DB");
 
+    tokio::spawn(orders::expire_reservations_every(
+        state.clone(),
+        app_config.reservation_timeout(),
+        app_config.expiry_check_interval(),
+    ));
+
     info!("Starting server");
 
     let version_router = Router::new().route(
@@ -83,10 +200,25 @@ async fn main() -> anyhow::Result<()> {
             (StatusCode::OK, Json(state.0.clone().version.clone()))
         }),
     );
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
+    let listener = TcpListener::bind(app_config.server_addr()).await?;
+    info!("Listening on {}", app_config.server_addr());
     let routes = Router::new()
+        .nest(media::MEDIA_PATH, media::api::create_router())
+        .nest("/cart", cart::api::create_router())
         .nest("/orders", orders::api::create_router())
+        .nest(
+            "/pets",
+            pet::api::create_router(app_config.max_upload_bytes()),
+        )
+        .nest("/promotions", promotion::api::create_router())
+        .nest("/store", store::api::create_router())
+        .nest("/tags", tag::api::create_router())
+        .nest("/users", user::api::create_router())
         .nest("/", version_router)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::authenticate,
+        ))
         .with_state(state.clone());
 
     axum::serve(listener, routes)
@@ -100,6 +232,21 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// `--profile <name>` on the command line takes precedence over
+/// `PETSTORE_PROFILE`.
+fn profile_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(profile) = arg.strip_prefix("--profile=") {
+            return Some(profile.to_string());
+        }
+        if arg == "--profile" {
+            return args.next();
+        }
+    }
+    None
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
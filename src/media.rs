@@ -0,0 +1,396 @@
+use std::{io::Cursor, sync::Arc};
+
+use anyhow::Result;
+use image::{codecs::jpeg::JpegEncoder, ImageFormat};
+
+use crate::config::AppConfig;
+
+use self::storage::{BlobStore, LocalBlobStore};
+
+/// Where uploaded files go and how large a single one may be.
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub blobs: Arc<dyn BlobStore>,
+    pub max_upload_bytes: usize,
+}
+
+impl From<&AppConfig> for Media {
+    fn from(config: &AppConfig) -> Self {
+        Media {
+            blobs: Arc::new(LocalBlobStore::new(config.media_dir())),
+            max_upload_bytes: config.max_upload_bytes(),
+        }
+    }
+}
+
+/// Prefix of the urls [`api::create_router`] serves blobs under.
+pub const MEDIA_PATH: &str = "/media";
+
+/// The image formats accepted for upload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageType {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageType {
+    const ALL: [ImageType; 4] = [
+        ImageType::Png,
+        ImageType::Jpeg,
+        ImageType::Gif,
+        ImageType::Webp,
+    ];
+
+    /// Tells the format from the leading bytes of the file, never trusting
+    /// the content type or file name the client sent.
+    pub fn sniff(bytes: &[u8]) -> Option<ImageType> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageType::Png)
+        } else if bytes.starts_with(b"\xff\xd8\xff") {
+            Some(ImageType::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(ImageType::Gif)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(ImageType::Webp)
+        } else {
+            None
+        }
+    }
+
+    /// The format stored under `key`, judged by its extension.
+    pub fn from_key(key: &str) -> Option<ImageType> {
+        let (_, extension) = key.rsplit_once('.')?;
+        Self::ALL
+            .into_iter()
+            .find(|image| image.extension() == extension)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageType::Png => "png",
+            ImageType::Jpeg => "jpg",
+            ImageType::Gif => "gif",
+            ImageType::Webp => "webp",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            ImageType::Png => "image/png",
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Gif => "image/gif",
+            ImageType::Webp => "image/webp",
+        }
+    }
+}
+
+/// The url a blob stored under `key` is served at.
+pub fn url(key: &str) -> String {
+    format!("{MEDIA_PATH}/{key}")
+}
+
+/// The key of a blob served at `url`, `None` for urls pointing elsewhere.
+pub fn key(url: &str) -> Option<&str> {
+    url.strip_prefix(MEDIA_PATH)?
+        .strip_prefix('/')
+        .filter(|key| is_valid_key(key))
+}
+
+/// Keys are relative paths of `a-z`, `0-9`, `-`, `_` and `.`, e.g.
+/// `pets/3/1c0e….png`, so they can neither escape the store nor need escaping
+/// in urls.
+pub fn is_valid_key(key: &str) -> bool {
+    key.split('/').all(|segment| {
+        !segment.is_empty()
+            && !segment.starts_with('.')
+            && segment.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.')
+            })
+    })
+}
+
+/// The boxes thumbnails are fitted into, in pixels.
+pub const THUMBNAIL_SIZES: [u32; 2] = [128, 512];
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+
+/// A scaled down copy of an uploaded image.
+#[derive(Debug)]
+pub struct Thumbnail {
+    pub size: u32,
+    pub image: ImageType,
+    pub bytes: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Where the thumbnail of the blob stored under `key` goes, next to it.
+    pub fn key(&self, key: &str) -> String {
+        let stem = key.rsplit_once('.').map_or(key, |(stem, _)| stem);
+        format!("{stem}-{}.{}", self.size, self.image.extension())
+    }
+}
+
+/// Fits `bytes` into each of the [`THUMBNAIL_SIZES`], keeping the aspect ratio
+/// and never scaling up. Images with transparency become lossless WebP, all
+/// others JPEG. Decoding and resizing is CPU bound, keep it off the async
+/// workers.
+pub fn thumbnails(bytes: &[u8]) -> Result<Vec<Thumbnail>> {
+    let original = image::load_from_memory(bytes)?;
+    let mut thumbnails = Vec::new();
+    for size in THUMBNAIL_SIZES {
+        let resized = if original.width() <= size && original.height() <= size {
+            original.clone()
+        } else {
+            original.thumbnail(size, size)
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        let image = if resized.color().has_alpha() {
+            resized.write_to(&mut bytes, ImageFormat::WebP)?;
+            ImageType::Webp
+        } else {
+            JpegEncoder::new_with_quality(&mut bytes, THUMBNAIL_JPEG_QUALITY)
+                .encode_image(&resized.to_rgb8())?;
+            ImageType::Jpeg
+        };
+        thumbnails.push(Thumbnail {
+            size,
+            image,
+            bytes: bytes.into_inner(),
+        });
+    }
+    Ok(thumbnails)
+}
+
+mod service {
+    use axum::{
+        extract::{Path, State},
+        http::{header, StatusCode},
+        response::IntoResponse,
+    };
+
+    use crate::{error::AppError, AppState};
+
+    use super::{is_valid_key, ImageType};
+
+    pub async fn get_media(
+        state: State<AppState>,
+        Path(key): Path<String>,
+    ) -> Result<impl IntoResponse, AppError> {
+        if !is_valid_key(&key) {
+            return Err(AppError::NotFound("media"));
+        }
+        let bytes = state
+            .media
+            .blobs
+            .get(&key)
+            .await?
+            .ok_or(AppError::NotFound("media"))?;
+        let content_type = ImageType::from_key(&key)
+            .map(ImageType::mime)
+            .unwrap_or("application/octet-stream");
+        Ok((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                // keys are never reused for other content
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ],
+            bytes,
+        ))
+    }
+}
+
+pub(crate) mod storage {
+    use std::{
+        fmt::Debug,
+        io::ErrorKind,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{bail, Result};
+    use axum::{async_trait, body::Bytes};
+
+    use super::is_valid_key;
+
+    /// Keeps uploaded files, addressed by keys as checked by [`is_valid_key`].
+    #[async_trait]
+    pub trait BlobStore: Debug + Send + Sync {
+        /// Stores `bytes` under `key`, replacing what was there.
+        async fn put(&self, key: &str, bytes: Bytes) -> Result<()>;
+        async fn get(&self, key: &str) -> Result<Option<Bytes>>;
+        /// Deleting a missing blob is not an error.
+        async fn delete(&self, key: &str) -> Result<()>;
+    }
+
+    /// Stores blobs as files below `root`, the key being the relative path.
+    #[derive(Debug, Clone)]
+    pub struct LocalBlobStore {
+        root: PathBuf,
+    }
+
+    impl LocalBlobStore {
+        pub fn new(root: impl AsRef<Path>) -> LocalBlobStore {
+            LocalBlobStore {
+                root: root.as_ref().to_path_buf(),
+            }
+        }
+
+        fn path(&self, key: &str) -> Result<PathBuf> {
+            if !is_valid_key(key) {
+                bail!("invalid blob key {key:?}");
+            }
+            Ok(self.root.join(key))
+        }
+    }
+
+    #[async_trait]
+    impl BlobStore for LocalBlobStore {
+        #[tracing::instrument(skip(self, bytes))]
+        async fn put(&self, key: &str, bytes: Bytes) -> Result<()> {
+            let path = self.path(key)?;
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            // readers never see a half written file
+            let partial = path.with_extension("partial");
+            tokio::fs::write(&partial, &bytes).await?;
+            tokio::fs::rename(&partial, &path).await?;
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+            match tokio::fs::read(self.path(key)?).await {
+                Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn delete(&self, key: &str) -> Result<()> {
+            match tokio::fs::remove_file(self.path(key)?).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+pub mod api {
+    use axum::{routing::get, Router};
+
+    use crate::AppState;
+
+    use super::service;
+
+    pub(crate) fn create_router() -> Router<AppState> {
+        Router::new().route("/*key", get(service::get_media))
+    }
+}
+
+#[cfg(test)]
+impl Media {
+    /// Media kept in a fresh directory below the system's temp dir.
+    pub fn temporary() -> Media {
+        let root = std::env::temp_dir().join(format!("pet-store-media-{}", uuid::Uuid::new_v4()));
+        Media {
+            blobs: Arc::new(LocalBlobStore::new(root)),
+            max_upload_bytes: 64 * 1024,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use axum::body::Bytes;
+    use image::{DynamicImage, GenericImageView, ImageFormat};
+
+    use super::{is_valid_key, key, thumbnails, ImageType, Media};
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn sniff_images() {
+        assert_eq!(Some(ImageType::Png), ImageType::sniff(PNG));
+        assert_eq!(Some(ImageType::Jpeg), ImageType::sniff(b"\xff\xd8\xff\xe0"));
+        assert_eq!(
+            Some(ImageType::Webp),
+            ImageType::sniff(b"RIFF\0\0\0\0WEBPVP8 ")
+        );
+        assert_eq!(
+            None,
+            ImageType::sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>")
+        );
+        assert_eq!(None, ImageType::sniff(b"RIFF"));
+
+        assert_eq!(Some(ImageType::Jpeg), ImageType::from_key("pets/1/a.jpg"));
+        assert_eq!(None, ImageType::from_key("pets/1/a"));
+    }
+
+    #[test]
+    fn keys_stay_inside_the_store() {
+        assert!(is_valid_key("pets/12/5f1d-4c2e.png"));
+        assert!(!is_valid_key("../etc/passwd"));
+        assert!(!is_valid_key("/etc/passwd"));
+        assert!(!is_valid_key("pets//a.png"));
+        assert!(!is_valid_key("pets/.hidden"));
+        assert!(!is_valid_key("pets/A.png"));
+
+        assert_eq!(Some("pets/1/a.png"), key("/media/pets/1/a.png"));
+        assert_eq!(None, key("https://example.com/media/pets/1/a.png"));
+        assert_eq!(None, key("/media/../config.toml"));
+    }
+
+    #[test]
+    fn scale_down_thumbnails() -> anyhow::Result<()> {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(1024, 256).write_to(&mut png, ImageFormat::Png)?;
+        let mut transparent = Cursor::new(Vec::new());
+        DynamicImage::new_rgba8(64, 32).write_to(&mut transparent, ImageFormat::Png)?;
+
+        let scaled = thumbnails(png.get_ref())?;
+        let sizes: Vec<(u32, u32)> = scaled
+            .iter()
+            .map(|thumbnail| image::load_from_memory(&thumbnail.bytes).map(|i| i.dimensions()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(vec![(128, 32), (512, 128)], sizes);
+        assert_eq!(ImageType::Jpeg, scaled[0].image);
+        assert_eq!("pets/1/a-128.jpg", scaled[0].key("pets/1/a.png"));
+
+        let kept = thumbnails(transparent.get_ref())?;
+        assert_eq!(ImageType::Webp, kept[1].image);
+        assert_eq!(
+            (64, 32),
+            image::load_from_memory(&kept[1].bytes)?.dimensions()
+        );
+        assert!(thumbnails(PNG).is_err(), "only the header of a png");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_blob_store() -> anyhow::Result<()> {
+        let media = Media::temporary();
+        let blobs = media.blobs;
+
+        assert_eq!(None, blobs.get("pets/1/a.png").await?);
+        blobs.put("pets/1/a.png", Bytes::from_static(PNG)).await?;
+        assert_eq!(
+            Some(Bytes::from_static(PNG)),
+            blobs.get("pets/1/a.png").await?
+        );
+
+        blobs.delete("pets/1/a.png").await?;
+        blobs.delete("pets/1/a.png").await?;
+        assert_eq!(None, blobs.get("pets/1/a.png").await?);
+        assert!(blobs
+            .put("../a.png", Bytes::from_static(PNG))
+            .await
+            .is_err());
+        Ok(())
+    }
+}
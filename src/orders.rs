@@ -4,253 +4,1614 @@ use pet::PetSize;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-use crate::pet;
+use crate::{
+    error::FieldError,
+    pet::{self, Price},
+    validation::{Errors, Validate},
+};
 
 #[derive(Serialize, Deserialize, sqlx::Type, PartialEq, Debug, Clone)]
 #[sqlx(type_name = "varchar", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
-enum OrderStatus {
+pub enum OrderStatus {
+    /// Placed for a kind of pet none of which was available, waits for one.
+    BackOrdered,
     Awaiting,
     Approved,
     Delivered,
     Cancelled,
 }
 
+/// Every status change an order may go through, anything else is rejected.
+/// `Delivered` and `Cancelled` are final.
+const TRANSITIONS: &[(OrderStatus, OrderStatus)] = &[
+    (OrderStatus::BackOrdered, OrderStatus::Awaiting),
+    (OrderStatus::BackOrdered, OrderStatus::Cancelled),
+    (OrderStatus::Awaiting, OrderStatus::Approved),
+    (OrderStatus::Awaiting, OrderStatus::Cancelled),
+    (OrderStatus::Approved, OrderStatus::Delivered),
+    (OrderStatus::Approved, OrderStatus::Cancelled),
+];
+
+impl OrderStatus {
+    pub fn can_transition_to(&self, next: &OrderStatus) -> bool {
+        TRANSITIONS
+            .iter()
+            .any(|(from, to)| from == self && to == next)
+    }
+
+    /// Whether the order is done with, `Delivered` or `Cancelled`.
+    pub fn is_final(&self) -> bool {
+        !TRANSITIONS.iter().any(|(from, _)| from == self)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct OrderTransition {
+    from: OrderStatus,
+    to: OrderStatus,
+    actor_id: u64,
+    changed_at: DateTime<Utc>,
+}
+
 #[derive(Serialize, Deserialize, FromRow, PartialEq, Debug, Clone)]
 pub struct Order {
+    /// Assigned by the server, whatever the client sends is ignored.
+    #[serde(default)]
     id: u64,
+    /// Always the caller placing the order.
+    #[serde(default)]
     user_id: u64,
-    pet_id: u64,
-    quantity: u64,
+    /// What is ordered, one pet per item.
+    #[serde(default)]
+    #[sqlx(skip)]
+    items: Vec<OrderItem>,
+    /// The single item of orders as placed before there were `items`, still
+    /// accepted in place of them and filled in for orders of one item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pet_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pet: Option<OrderPet>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    quantity: Option<u64>,
+    /// The quantities of all items added up, computed by the server.
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    total_quantity: u64,
+    /// A coupon code to redeem when placing the order, see [`Promotion`].
+    /// Never returned, the `discounts` show what it gave.
+    ///
+    /// [`Promotion`]: crate::promotion::Promotion
+    #[serde(default, skip_serializing)]
+    #[sqlx(skip)]
+    coupon: Option<String>,
+    /// The promotions the order got when it was placed.
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    discounts: Vec<OrderDiscount>,
+    /// The currency of `subtotal`, `discount`, `tax` and `total`, `None` while
+    /// no item has a price.
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    currency: Option<String>,
+    /// The prices of the items times their quantities, in the minor unit of
+    /// `currency`. Computed by the server, as are `tax` and `total`.
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    subtotal: u64,
+    /// The `discounts` added up, at most the `subtotal`. Taxed is what is left.
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    discount: u64,
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    tax: u64,
+    #[serde(default, skip_deserializing)]
+    #[sqlx(skip)]
+    total: u64,
     ship_date: Option<DateTime<Utc>>,
-    status: OrderStatus,
+    /// Set by the server, orders are placed `awaiting` or `backordered`. When
+    /// an update gives it, it has to be the current one.
+    #[serde(default)]
+    status: Option<OrderStatus>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct OrderPet {
-    category: PetCategory,
-    pet_size: PetSize,
+impl Order {
+    fn has_single_item_fields(&self) -> bool {
+        self.pet_id.is_some() || self.pet.is_some() || self.quantity.is_some()
+    }
+
+    /// `items`, or the one item given by `pet_id`, `pet` and `quantity`.
+    fn ordered_items(&self) -> Vec<OrderItem> {
+        if !self.items.is_empty() {
+            return self.items.clone();
+        }
+        vec![OrderItem {
+            id: 0,
+            pet_id: self.pet_id,
+            pet: self.pet.clone(),
+            quantity: self.quantity.unwrap_or_default(),
+            unit_price: None,
+        }]
+    }
 }
+
+impl Validate for Order {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = if self.items.is_empty() {
+            self.ordered_items()[0].validate()
+        } else {
+            let mut errors = Vec::new();
+            for (i, item) in self.items.iter().enumerate() {
+                errors.extend(item.validate().into_iter().map(|error| FieldError {
+                    field: "items",
+                    message: format!("item {}: {} {}", i + 1, error.field, error.message),
+                }));
+            }
+            errors
+        };
+        errors.extend(
+            Errors::default()
+                .check(
+                    self.items.is_empty() || !self.has_single_item_fields(),
+                    "items",
+                    "cannot be combined with pet_id, pet or quantity",
+                )
+                .check(
+                    self.ship_date.is_none_or(|date| date >= Utc::now()),
+                    "ship_date",
+                    "must not be in the past",
+                )
+                .check(
+                    self.coupon
+                        .as_ref()
+                        .is_none_or(|code| !code.trim().is_empty()),
+                    "coupon",
+                    "must not be empty",
+                )
+                .finish(),
+        );
+        errors
+    }
+}
+
+/// How many pets of a kind one item may ask for.
+pub(crate) const MAX_QUANTITY: u64 = 100;
+
+/// One line of an order, either a particular pet or a kind of pet.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct OrderItem {
+    /// Assigned by the server, whatever the client sends is ignored.
+    #[serde(default)]
+    id: u64,
+    /// The pet ordered, or allocated to an item ordered by `pet`. `None`
+    /// while back-ordered.
+    #[serde(default)]
+    pet_id: Option<u64>,
+    /// The kind of pet ordered when the buyer has no particular one in mind,
+    /// only looked at when there is no `pet_id`.
+    #[serde(default)]
+    pet: Option<OrderPet>,
+    /// Always 1 for a particular pet. A kind ordered several times is placed
+    /// as that many items of one pet each.
+    quantity: u64,
+    /// What the pet cost when it was reserved for the order, `None` while
+    /// back-ordered or for pets without a price. Ignored on input.
+    #[serde(default, skip_deserializing)]
+    unit_price: Option<Price>,
+}
+
+impl Validate for OrderItem {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(
+                self.pet_id.is_some() || self.pet.is_some(),
+                "pet_id",
+                "either pet_id or pet must be given",
+            )
+            .check(self.quantity >= 1, "quantity", "must be at least 1")
+            .check(
+                self.pet_id.is_none() || self.quantity <= 1,
+                "quantity",
+                "must be 1 for a particular pet",
+            )
+            .check(
+                self.quantity <= MAX_QUANTITY,
+                "quantity",
+                &format!("must be at most {MAX_QUANTITY}"),
+            )
+            .finish()
+    }
+}
+
+/// A discount an order got, `promotion_id` is `None` once the promotion is
+/// deleted.
+#[derive(Serialize, PartialEq, Debug, Clone)]
+pub struct OrderDiscount {
+    promotion_id: Option<u64>,
+    code: Option<String>,
+    amount: u64,
+    free_shipping: bool,
+}
+
+pub(crate) use service::{expire_reservations_every, fill_back_orders, place_order};
+
+/// A kind of pet, e.g. a House sized Feline, which the store picks one of.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub(crate) struct OrderPet {
+    pub category: PetCategory,
+    pub pet_size: PetSize,
+}
+
 mod service {
-    use axum::{debug_handler, routing::get, Router};
+    use std::time::Duration;
+
+    use axum::debug_handler;
     use axum::{
         extract::{Path, State},
-        http::StatusCode,
+        http::{header, StatusCode},
         response::IntoResponse,
         Json,
     };
 
-    use crate::{error::AppError, AppState};
-    use anyhow::Result;
+    use crate::{
+        auth::{AuthUser, Permission},
+        error::{is_foreign_key_violation, AppError, FieldError},
+        persistence::UnitOfWork,
+        pet::{
+            storage::{PetDB, PetFilter, PetSort, PetSortKey},
+            PetStatus,
+        },
+        promotion::{apply_promotions, OrderLine},
+        validation::Valid,
+        AppState,
+    };
+    use chrono::{DateTime, Utc};
 
     use super::{
-        storage::{self, OrderDB},
-        Order,
+        storage::{OrderDB, OrderDiscountDB, OrderItemDB, OrderRepository},
+        Order, OrderPet, OrderStatus, OrderTransition,
     };
-    #[debug_handler]
-    pub async fn get_order(state: State<AppState>, Path(order_id): Path<u64>) -> impl IntoResponse {
-        match storage::get(state.0.clone(), order_id).await {
-            Ok(Some(order)) => (StatusCode::OK, Json::<Order>(order.into())).into_response(),
-            Ok(None) => (StatusCode::NOT_FOUND, Json(())).into_response(),
-            Err(err) => AppError(err).into_response(),
+
+    /// How many available pets of a kind [`allocate`] tries to reserve before
+    /// giving up, others may be reserving the same ones.
+    const ALLOCATION_CANDIDATES: u64 = 10;
+
+    /// Recorded as the actor of the status changes the store makes on its own,
+    /// user ids start at 1.
+    const SYSTEM_ACTOR: u64 = 0;
+
+    /// Customers may only touch their own orders, staff may touch anyone's.
+    fn check_owner(auth: &AuthUser, user_id: u64) -> Result<(), AppError> {
+        if user_id == auth.id {
+            return Ok(());
         }
+        auth.require(Permission::ManageAllOrders)
     }
 
-    pub async fn delete(state: State<AppState>, Path(order_id): Path<u64>) -> impl IntoResponse {
-        match storage::delete(state.0.clone(), order_id).await {
-            Ok(_) => (StatusCode::OK, Json(())).into_response(),
-            Err(err) => AppError(err).into_response(),
+    /// Fetches `order_id` from `orders`, those of the state or of a unit of
+    /// work about to change the order, making sure `auth` is allowed to see it.
+    async fn find_order(
+        orders: &dyn OrderRepository,
+        auth: &AuthUser,
+        order_id: u64,
+    ) -> Result<OrderDB, AppError> {
+        let order = orders
+            .get(order_id)
+            .await?
+            .ok_or(AppError::NotFound("order"))?;
+        check_owner(auth, order.user_id as u64)?;
+        Ok(order)
+    }
+
+    fn no_such_pet() -> AppError {
+        AppError::Validation(vec![FieldError {
+            field: "pet_id",
+            message: "does not reference an existing pet".to_string(),
+        }])
+    }
+
+    /// `user_id` always belongs to a known caller, so a broken reference can
+    /// only be the pet.
+    fn unknown_pet(err: anyhow::Error) -> AppError {
+        if is_foreign_key_violation(&err) {
+            no_such_pet()
+        } else {
+            AppError::Internal(err)
+        }
+    }
+
+    fn illegal_transition(from: &OrderStatus, to: &OrderStatus) -> AppError {
+        AppError::Conflict {
+            code: "illegal_status_transition",
+            detail: format!("an order cannot go from {from:?} to {to:?}").to_lowercase(),
         }
     }
 
+    #[debug_handler]
+    pub async fn get_order(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let order = find_order(state.orders.as_ref(), &auth, order_id).await?;
+        Ok((StatusCode::OK, Json::<Order>(order.into())))
+    }
+
+    /// Cancels the order rather than dropping it, so that its history stays.
+    /// Deleting a cancelled order again changes nothing.
+    pub async fn delete(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let uow = state.begin().await?;
+        let order = find_order(uow.orders.as_ref(), &auth, order_id).await?;
+        let order = if order.status == OrderStatus::Cancelled {
+            order
+        } else {
+            cancel_order(&state, uow, &auth, &order).await?
+        };
+        Ok((StatusCode::OK, Json(Order::from(order))))
+    }
+
     pub async fn list_orders(
         state: State<AppState>,
+        auth: AuthUser,
         Path(user_id): Path<u64>,
-    ) -> impl IntoResponse {
-        match storage::list(state.0.clone(), user_id).await {
-            Ok(res) => (
-                StatusCode::OK,
-                Json::<Vec<Order>>(res.into_iter().map(|o| o.into()).collect()),
-            )
-                .into_response(),
+    ) -> Result<impl IntoResponse, AppError> {
+        check_owner(&auth, user_id)?;
+        let orders = state.orders.list(user_id).await?;
+        Ok((
+            StatusCode::OK,
+            Json::<Vec<Order>>(orders.into_iter().map(|o| o.into()).collect()),
+        ))
+    }
+
+    /// Whether `pet` can join an order priced in `currency`, all prices of an
+    /// order are in one currency. Pets without a price fit any order.
+    fn fits_currency(pet: &PetDB, currency: Option<&str>) -> bool {
+        pet.currency
+            .as_deref()
+            .zip(currency)
+            .is_none_or(|(pet, order)| pet == order)
+    }
+
+    /// Assigns the reserved `pet` to `item` at the price it has now.
+    fn assign(item: &mut OrderItemDB, pet: &PetDB) {
+        item.pet_id = Some(pet.id);
+        item.unit_price = pet.price;
+        item.currency = pet.currency.clone();
+    }
 
-            Err(err) => AppError(err).into_response(),
+    /// The amounts of an [`Order`], see [`totals`].
+    pub(super) struct Totals {
+        pub currency: Option<String>,
+        pub subtotal: u64,
+        pub discount: u64,
+        pub tax: u64,
+        pub total: u64,
+    }
+
+    /// What the item costs at the price it was reserved at, `0` without one.
+    fn line_price(item: &OrderItemDB) -> u64 {
+        item.unit_price.map_or(0, |price| {
+            (price as u64).saturating_mul(item.quantity as u64)
+        })
+    }
+
+    /// Adds up the prices the pets of `order` were reserved at, takes off its
+    /// discounts and puts the tax rate of the order on top, rounding the tax
+    /// half up to the minor unit. Items without a price count as free.
+    pub(super) fn totals(order: &OrderDB) -> Totals {
+        let subtotal = order
+            .items
+            .iter()
+            .map(line_price)
+            .fold(0u64, u64::saturating_add);
+        let discount = order
+            .discounts
+            .iter()
+            .map(|discount| discount.amount as u64)
+            .fold(0u64, u64::saturating_add)
+            .min(subtotal);
+        let taxed = subtotal - discount;
+        let tax = (taxed as u128 * order.tax_rate as u128 + 5_000) / 10_000;
+        let tax = u64::try_from(tax).unwrap_or(u64::MAX);
+        Totals {
+            currency: order.items.iter().find_map(|item| item.currency.clone()),
+            subtotal,
+            discount,
+            tax,
+            total: taxed.saturating_add(tax),
+        }
+    }
+
+    /// Reserves the oldest available pet of the `kind` priced in `currency`,
+    /// marking it `Pending`. Returns `None` when there is no such pet.
+    async fn allocate(
+        uow: &UnitOfWork,
+        kind: &OrderPet,
+        currency: Option<&str>,
+    ) -> anyhow::Result<Option<PetDB>> {
+        let filter = PetFilter {
+            status: Some(PetStatus::Available),
+            category: Some(kind.category.clone()),
+            size: Some(kind.pet_size.clone()),
+            tag: None,
+            currency: currency.map(str::to_string),
+        };
+        let oldest = PetSort {
+            key: PetSortKey::Id,
+            descending: false,
+        };
+        let (candidates, _) = uow
+            .pets
+            .search(&filter, oldest, ALLOCATION_CANDIDATES, 0)
+            .await?;
+        for pet in candidates {
+            let reserved = uow
+                .pets
+                .update_status(pet.id as u64, PetStatus::Available, PetStatus::Pending)
+                .await?;
+            if reserved {
+                return Ok(Some(pet));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reserves the pet with `pet_id`, marking it `Pending`, and returns it.
+    async fn reserve(uow: &UnitOfWork, pet_id: u64) -> Result<PetDB, AppError> {
+        let reserved = uow
+            .pets
+            .update_status(pet_id, PetStatus::Available, PetStatus::Pending)
+            .await?;
+        match uow.pets.get(pet_id).await? {
+            Some(pet) if reserved => Ok(pet),
+            Some(_) => Err(AppError::Conflict {
+                code: "pet_unavailable",
+                detail: format!("pet {pet_id} is not available"),
+            }),
+            None => Err(no_such_pet()),
+        }
+    }
+
+    /// Allocates pets to the items of back-orders that are waiting for their
+    /// kind, oldest order first. Orders with a pet for every item move on to
+    /// `Awaiting`. To be called whenever pets may have become available.
+    pub(crate) async fn fill_back_orders(state: &AppState, actor_id: u64) -> anyhow::Result<()> {
+        let uow = state.begin().await?;
+        for mut order in uow.orders.back_orders().await? {
+            let mut allocated = false;
+            let mut currency = order.items.iter().find_map(|item| item.currency.clone());
+            for item in order.items.iter_mut().filter(|item| item.pet_id.is_none()) {
+                let Some(kind) = item.pet() else {
+                    continue;
+                };
+                if let Some(pet) = allocate(&uow, &kind, currency.as_deref()).await? {
+                    assign(item, &pet);
+                    currency = currency.or(pet.currency);
+                    allocated = true;
+                }
+            }
+            if !allocated {
+                continue;
+            }
+            let complete = order.items.iter().all(|item| item.pet_id.is_some());
+            let order_id = order.id as u64;
+            uow.orders
+                .update(OrderDB {
+                    // a partial hold runs from the first pet, a complete one anew
+                    reserved_at: if complete {
+                        Some(Utc::now())
+                    } else {
+                        order.reserved_at.or_else(|| Some(Utc::now()))
+                    },
+                    ..order
+                })
+                .await?;
+            if complete {
+                uow.orders
+                    .transition(
+                        order_id,
+                        OrderStatus::BackOrdered,
+                        OrderStatus::Awaiting,
+                        actor_id,
+                    )
+                    .await?;
+            }
         }
-        // let orders = storage::list(state, user_id).await?;
-        // Ok(orders.into_iter().map(|o| o.into()).collect())
+        uow.commit().await
     }
+
+    /// [`fill_back_orders`] after a pet was released, the release is committed
+    /// already, so a failure is only logged.
+    async fn refill_back_orders(state: &AppState, actor_id: u64) {
+        if let Err(err) = fill_back_orders(state, actor_id).await {
+            tracing::warn!("could not fill back-orders: {err:?}");
+        }
+    }
+
+    /// Puts the pets reserved for the cancelled `order` back on sale and gives
+    /// the uses of its promotions back. Returns `false` when the order held no
+    /// reservation.
+    async fn release(uow: &UnitOfWork, order: &OrderDB) -> anyhow::Result<bool> {
+        let mut released = false;
+        for pet_id in order.reserved_pets() {
+            released |= uow
+                .pets
+                .update_status(pet_id, PetStatus::Pending, PetStatus::Available)
+                .await?;
+        }
+        for promotion_id in order.discounts.iter().filter_map(|d| d.promotion_id) {
+            uow.promotions.return_use(promotion_id as u64).await?;
+        }
+        Ok(released)
+    }
+
+    /// Cancels the `Awaiting` orders whose pets were reserved before
+    /// `reserved_before`, and the back-orders which have held some of their
+    /// pets since then, releasing them. Returns how many expired.
+    pub(crate) async fn expire_reservations(
+        state: &AppState,
+        reserved_before: DateTime<Utc>,
+    ) -> anyhow::Result<usize> {
+        let uow = state.begin().await?;
+        let mut expired = 0;
+        for order in uow.orders.expired(reserved_before).await? {
+            let cancelled = uow
+                .orders
+                .transition(
+                    order.id as u64,
+                    order.status.clone(),
+                    OrderStatus::Cancelled,
+                    SYSTEM_ACTOR,
+                )
+                .await?;
+            if cancelled.is_some() {
+                release(&uow, &order).await?;
+                expired += 1;
+            }
+        }
+        uow.commit().await?;
+        if expired > 0 {
+            fill_back_orders(state, SYSTEM_ACTOR).await?;
+        }
+        Ok(expired)
+    }
+
+    /// Every `interval`, for as long as the server runs, expires the
+    /// reservations of orders not approved within `timeout`.
+    pub(crate) async fn expire_reservations_every(
+        state: AppState,
+        timeout: Duration,
+        interval: Duration,
+    ) {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match expire_reservations(&state, Utc::now() - timeout).await {
+                Ok(0) => {}
+                Ok(expired) => tracing::info!("expired {expired} order reservations"),
+                Err(err) => tracing::warn!("could not expire order reservations: {err:?}"),
+            }
+        }
+    }
+
+    /// Places an order of `items` for `user_id` within `uow`, without
+    /// committing it. An item given by `pet_id` has to be available, for one
+    /// given by `pet_category` and `pet_size` the store picks `quantity`
+    /// available ones, one item each, and back-orders the order for those it
+    /// is short of. The pets are marked
+    /// `Pending` and stay reserved until the order is delivered, cancelled or
+    /// expires. Each item keeps the price its pet has when reserved, the order
+    /// `tax_rate_bps`, and all pets of an order have to be priced in the same
+    /// currency. The order gets the discounts of the running promotions it
+    /// qualifies for, and of its `coupon`, unless it is back-ordered: without
+    /// the prices of all its pets there is nothing to work them out from, so
+    /// a back-order gets no promotions and a coupon is rejected.
+    pub(crate) async fn place_order(
+        uow: &UnitOfWork,
+        user_id: u64,
+        tax_rate_bps: u32,
+        items: Vec<OrderItemDB>,
+        ship_date: Option<DateTime<Utc>>,
+        coupon: Option<&str>,
+    ) -> Result<OrderDB, AppError> {
+        let mut placed = Vec::new();
+        let mut lines = Vec::new();
+        let mut currency: Option<String> = None;
+        let items = items.into_iter().flat_map(|item| {
+            let copies = if item.pet_id.is_some() {
+                1
+            } else {
+                item.quantity as usize
+            };
+            std::iter::repeat_n(
+                OrderItemDB {
+                    quantity: 1,
+                    ..item
+                },
+                copies,
+            )
+        });
+        for mut item in items {
+            let mut line = OrderLine {
+                category: item.pet_category.clone(),
+                tags: Vec::new(),
+                quantity: 1,
+                amount: 0,
+            };
+            let pet = match (item.pet_id, item.pet()) {
+                (Some(pet_id), _) => {
+                    let pet = reserve(uow, pet_id as u64).await?;
+                    if !fits_currency(&pet, currency.as_deref()) {
+                        return Err(AppError::Conflict {
+                            code: "currency_mismatch",
+                            detail: format!(
+                                "pet {pet_id} is not priced in {}, the currency of the order",
+                                currency.unwrap_or_default()
+                            ),
+                        });
+                    }
+                    Some(pet)
+                }
+                (None, Some(kind)) => allocate(uow, &kind, currency.as_deref()).await?,
+                (None, None) => unreachable!("checked by validate"),
+            };
+            if let Some(pet) = pet {
+                assign(&mut item, &pet);
+                line.category = pet.category;
+                line.tags = pet.tags.into_iter().map(|tag| tag.name).collect();
+                line.amount = line_price(&item);
+                currency = currency.or(pet.currency);
+            }
+            placed.push(item);
+            lines.push(line);
+        }
+        let allocated = placed.iter().all(|item| item.pet_id.is_some());
+        // what the pets still missing cost is only known once they are found
+        if !allocated && coupon.is_some() {
+            return Err(AppError::Validation(vec![FieldError {
+                field: "coupon",
+                message: "cannot be redeemed on an order that has to be back-ordered".to_string(),
+            }]));
+        }
+        let discounts = if allocated {
+            apply_promotions(uow, coupon, &lines, currency.as_deref()).await?
+        } else {
+            Vec::new()
+        };
+        let discounts = discounts
+            .into_iter()
+            .map(|discount| OrderDiscountDB {
+                id: 0,
+                order_id: 0,
+                promotion_id: Some(discount.promotion_id as i64),
+                code: discount.code,
+                amount: discount.amount as i64,
+                free_shipping: discount.free_shipping,
+            })
+            .collect();
+        let order_db = OrderDB {
+            id: 0,
+            user_id: user_id as i64,
+            ship_date,
+            status: if allocated {
+                OrderStatus::Awaiting
+            } else {
+                OrderStatus::BackOrdered
+            },
+            reserved_at: placed
+                .iter()
+                .any(|item| item.pet_id.is_some())
+                .then(Utc::now),
+            tax_rate: tax_rate_bps as i64,
+            items: placed,
+            discounts,
+        };
+        uow.orders.create(order_db).await.map_err(unknown_pet)
+    }
+
+    /// Orders are always placed on behalf of the caller, whatever `user_id` says,
+    /// and start out `Awaiting`, or `BackOrdered`, see [`place_order`]. The
+    /// order gets the configured tax rate.
     pub async fn create_order(
         state: State<AppState>,
-        Json(order): Json<Order>,
-    ) -> impl IntoResponse {
+        auth: AuthUser,
+        Valid(order): Valid<Order>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let items = order
+            .ordered_items()
+            .into_iter()
+            .map(|item| {
+                // the kind only matters for items without a particular pet
+                let kind = item.pet.filter(|_| item.pet_id.is_none());
+                OrderItemDB {
+                    id: 0,
+                    order_id: 0,
+                    pet_id: item.pet_id.map(|pet_id| pet_id as i64),
+                    quantity: item.quantity as i64,
+                    pet_category: kind.as_ref().map(|kind| kind.category.clone()),
+                    pet_size: kind.map(|kind| kind.pet_size),
+                    unit_price: None,
+                    currency: None,
+                }
+            })
+            .collect();
+        let uow = state.begin().await?;
+        let order = place_order(
+            &uow,
+            auth.id,
+            state.tax_rate_bps,
+            items,
+            order.ship_date,
+            order.coupon.as_deref(),
+        )
+        .await?;
+        uow.commit().await?;
+        Ok((
+            StatusCode::CREATED,
+            [(header::LOCATION, format!("/orders/{}", order.id))],
+            Json::<Order>(order.into()),
+        ))
+    }
+
+    /// Edits the order details. The status can only be changed through the
+    /// dedicated action endpoints, see [`approve`], [`deliver`] and [`cancel`].
+    /// What was ordered cannot change: the pets are reserved for the order or
+    /// it is waiting for them, and its prices and discounts were settled when
+    /// it was placed. That leaves the ship date, until the order is final.
+    pub async fn update_order(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+        Valid(order): Valid<Order>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let existing = find_order(state.orders.as_ref(), &auth, order_id).await?;
+        if let Some(status) = order.status.as_ref().filter(|s| **s != existing.status) {
+            return Err(illegal_transition(&existing.status, status));
+        }
+        if existing.status.is_final() {
+            return Err(AppError::Conflict {
+                code: "order_closed",
+                detail: format!("a {:?} order cannot be changed", existing.status).to_lowercase(),
+            });
+        }
+        let items = order.ordered_items();
+        let same_pets = items.len() == existing.items.len()
+            && items.iter().zip(&existing.items).all(|(item, existing)| {
+                item.pet_id
+                    .is_none_or(|pet_id| existing.pet_id == Some(pet_id as i64))
+                    && item
+                        .pet
+                        .as_ref()
+                        .is_none_or(|kind| existing.pet().as_ref() == Some(kind))
+            });
+        if !same_pets {
+            return Err(AppError::Conflict {
+                code: "pet_fixed",
+                detail: "the pets of an order cannot be changed, cancel it and place a new one"
+                    .to_string(),
+            });
+        }
+        let same_quantities = items
+            .iter()
+            .zip(&existing.items)
+            .all(|(item, existing)| item.quantity == existing.quantity as u64);
+        if !same_quantities {
+            return Err(AppError::Conflict {
+                code: "quantity_fixed",
+                detail: "the quantities of an order cannot be changed, its discounts depend on \
+                    them"
+                    .to_string(),
+            });
+        }
+        // staff editing someone else's order keep it attributed to its owner
         let order_db = OrderDB {
-            id: order.id as i64,
-            user_id: order.user_id as i64,
-            pet_id: order.pet_id as i64,
-            quantity: order.quantity as i64,
             ship_date: order.ship_date,
-            status: order.status,
+            ..existing
         };
-        match storage::create(state.0.clone(), order_db)
+        let order = state
+            .orders
+            .update(order_db)
             .await
-            .map_err(|err| AppError(err))
-        {
-            Ok(_) => (StatusCode::OK, Json(())).into_response(),
-            Err(err) => err.into_response(),
+            .map_err(unknown_pet)?
+            .ok_or(AppError::NotFound("order"))?;
+        Ok((StatusCode::OK, Json::<Order>(order.into())))
+    }
+
+    /// Moves `order`, as found by [`find_order`], on to `to` within `uow`.
+    async fn transition(
+        uow: &UnitOfWork,
+        auth: &AuthUser,
+        order: &OrderDB,
+        to: OrderStatus,
+    ) -> Result<OrderDB, AppError> {
+        if !order.status.can_transition_to(&to) {
+            return Err(illegal_transition(&order.status, &to));
         }
+        uow.orders
+            .transition(order.id as u64, order.status.clone(), to.clone(), auth.id)
+            .await?
+            // somebody else moved the order in the meantime
+            .ok_or_else(|| illegal_transition(&order.status, &to))
     }
 
-    pub async fn update_order(
+    pub async fn approve(
         state: State<AppState>,
-        Json(order): Json<Order>,
-    ) -> impl IntoResponse {
-        let order_db = OrderDB {
-            id: order.id as i64,
-            user_id: order.user_id as i64,
-            pet_id: order.pet_id as i64,
-            quantity: order.quantity as i64,
-            ship_date: order.ship_date,
-            status: order.status,
-        };
-        match storage::update(state.0.clone(), order_db).await {
-            Ok(_) => (StatusCode::OK, Json(())).into_response(),
-            Err(err) => AppError(err).into_response(),
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let uow = state.begin().await?;
+        let order = find_order(uow.orders.as_ref(), &auth, order_id).await?;
+        let order = transition(&uow, &auth, &order, OrderStatus::Approved).await?;
+        uow.commit().await?;
+        Ok((StatusCode::OK, Json(Order::from(order))))
+    }
+
+    /// Hands the reserved pets over, marking them `Sold`.
+    pub async fn deliver(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let uow = state.begin().await?;
+        let order = find_order(uow.orders.as_ref(), &auth, order_id).await?;
+        for pet_id in order.reserved_pets() {
+            let sold = uow
+                .pets
+                .update_status(pet_id, PetStatus::Pending, PetStatus::Sold)
+                .await?;
+            if !sold {
+                tracing::warn!("pet {pet_id} of order {order_id} was no longer reserved");
+            }
         }
+        let order = transition(&uow, &auth, &order, OrderStatus::Delivered).await?;
+        uow.commit().await?;
+        Ok((StatusCode::OK, Json(Order::from(order))))
+    }
+
+    /// Puts the reserved pets, if any, back on sale.
+    pub async fn cancel(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let uow = state.begin().await?;
+        let order = find_order(uow.orders.as_ref(), &auth, order_id).await?;
+        let cancelled = cancel_order(&state, uow, &auth, &order).await?;
+        Ok((StatusCode::OK, Json(Order::from(cancelled))))
+    }
+
+    /// Cancels `order`, as found by [`find_order`] within `uow`, releasing its
+    /// pets.
+    async fn cancel_order(
+        state: &AppState,
+        uow: UnitOfWork,
+        auth: &AuthUser,
+        order: &OrderDB,
+    ) -> Result<OrderDB, AppError> {
+        let cancelled = transition(&uow, auth, order, OrderStatus::Cancelled).await?;
+        let released = release(&uow, order).await?;
+        uow.commit().await?;
+        if released {
+            refill_back_orders(state, auth.id).await;
+        }
+        Ok(cancelled)
+    }
+
+    pub async fn history(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(order_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        find_order(state.orders.as_ref(), &auth, order_id).await?;
+        let history = state.orders.history(order_id).await?;
+        Ok((
+            StatusCode::OK,
+            Json::<Vec<OrderTransition>>(history.into_iter().map(|t| t.into()).collect()),
+        ))
     }
 }
-mod storage {
+pub(crate) mod storage {
+    use std::fmt::Debug;
 
-    use axum::extract::State;
+    use axum::async_trait;
     use chrono::{DateTime, Utc};
-    use sqlx::{FromRow, Postgres};
+    use sqlx::{Connection, FromRow};
 
-    use crate::{persistence::ArcPgPool, user, AppState};
+    use crate::{
+        persistence::{foreign_key_violation, in_params, on_conn, MemoryDb, Sql, Tables},
+        pet::{PetCategory, PetSize, Price},
+    };
 
-    use super::{Order, OrderStatus};
+    use super::{Order, OrderDiscount, OrderItem, OrderPet, OrderStatus, OrderTransition};
     use anyhow::Result;
 
-    pub struct OrderStorage {
-        pub storage: ArcPgPool,
-    }
-
     #[derive(Clone, FromRow, PartialEq, Debug)]
     pub struct OrderDB {
         pub id: i64,
-        pub pet_id: i64,
         pub user_id: i64,
-        pub quantity: i64,
         pub ship_date: Option<DateTime<Utc>>,
         pub status: OrderStatus,
+        /// When the pets were reserved for the order, or when a back-order
+        /// got its first pet, `None` while it holds none.
+        pub reserved_at: Option<DateTime<Utc>>,
+        /// In basis points, as configured when the order was placed.
+        pub tax_rate: i64,
+        /// Kept in `order_items`, ordered by id.
+        #[sqlx(skip)]
+        pub items: Vec<OrderItemDB>,
+        /// Kept in `order_discounts`, ordered by id. Only written on create.
+        #[sqlx(skip)]
+        pub discounts: Vec<OrderDiscountDB>,
+    }
+
+    impl OrderDB {
+        /// The pets held `Pending` for this order, until it is delivered or
+        /// cancelled. Back-orders hold the pets allocated to them so far.
+        pub(super) fn reserved_pets(&self) -> Vec<u64> {
+            match self.status {
+                OrderStatus::BackOrdered | OrderStatus::Awaiting | OrderStatus::Approved => self
+                    .items
+                    .iter()
+                    .filter_map(|item| item.pet_id.map(|pet_id| pet_id as u64))
+                    .collect(),
+                OrderStatus::Delivered | OrderStatus::Cancelled => Vec::new(),
+            }
+        }
     }
 
-    impl Into<Order> for OrderDB {
-        fn into(self) -> Order {
+    impl From<OrderDB> for Order {
+        fn from(order: OrderDB) -> Self {
+            let totals = super::service::totals(&order);
+            let discounts = order
+                .discounts
+                .into_iter()
+                .map(OrderDiscount::from)
+                .collect();
+            let items: Vec<OrderItem> = order.items.into_iter().map(OrderItem::from).collect();
+            let single = match &items[..] {
+                [item] => Some(item.clone()),
+                _ => None,
+            };
             Order {
-                id: self.id as u64,
-                user_id: self.user_id as u64,
-                pet_id: self.pet_id as u64,
-                quantity: self.quantity as u64,
-                ship_date: self.ship_date,
-                status: self.status,
-            }
-        }
-    }
-
-    #[tracing::instrument(skip(state))]
-    pub async fn create(state: AppState, order: OrderDB) -> Result<()> {
-        sqlx::query::<Postgres>("insert into orders ( id, pet_id, user_id, quantity, ship_date, status) values ($1,$2,$3,$4,$5,$6);")
-            .bind(order.id)
-            .bind(order.pet_id)
-            .bind(order.user_id)
-            .bind(order.quantity)
-            .bind(order.ship_date)
-            .bind(order.status)
-            .execute(&state.db.clone())
-            .await?;
+                id: order.id as u64,
+                user_id: order.user_id as u64,
+                pet_id: single.as_ref().and_then(|item| item.pet_id),
+                pet: single.as_ref().and_then(|item| item.pet.clone()),
+                quantity: single.map(|item| item.quantity),
+                total_quantity: items.iter().map(|item| item.quantity).sum(),
+                coupon: None,
+                discounts,
+                currency: totals.currency,
+                subtotal: totals.subtotal,
+                discount: totals.discount,
+                tax: totals.tax,
+                total: totals.total,
+                items,
+                ship_date: order.ship_date,
+                status: Some(order.status),
+            }
+        }
+    }
 
-        Ok(())
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct OrderItemDB {
+        pub id: i64,
+        pub order_id: i64,
+        pub pet_id: Option<i64>,
+        pub quantity: i64,
+        /// The kind of pet ordered, see [`OrderPet`].
+        pub pet_category: Option<PetCategory>,
+        pub pet_size: Option<PetSize>,
+        /// The price of the pet when it was reserved, see [`PetDB::price`].
+        ///
+        /// [`PetDB::price`]: crate::pet::storage::PetDB::price
+        pub unit_price: Option<i64>,
+        pub currency: Option<String>,
     }
 
-    #[tracing::instrument(skip(state))]
-    pub async fn get(state: AppState, order_id: u64) -> Result<Option<OrderDB>> {
-        let res: Option<OrderDB> = sqlx::query_as(
-            "select * 
-            from orders o 
-            where o.id = $1",
-        )
-        .bind(order_id as i64)
-        .fetch_optional(&state.db.clone())
-        .await?;
+    impl OrderItemDB {
+        pub(super) fn pet(&self) -> Option<OrderPet> {
+            Some(OrderPet {
+                category: self.pet_category.clone()?,
+                pet_size: self.pet_size.clone()?,
+            })
+        }
 
-        Ok(res)
+        fn unit_price(&self) -> Option<Price> {
+            Some(Price {
+                amount: self.unit_price? as u64,
+                currency: self.currency.clone()?,
+            })
+        }
     }
 
-    #[tracing::instrument(skip(state))]
-    pub async fn list(state: AppState, user_id: u64) -> Result<Vec<OrderDB>> {
-        let res: Vec<OrderDB> = sqlx::query_as(
-            "select * 
-            from orders o 
-            where o.user_id = $1
-            order by o.id desc",
-        )
-        .bind(user_id as i64)
-        .fetch_all(&state.db.clone())
-        .await?;
+    impl From<OrderItemDB> for OrderItem {
+        fn from(item: OrderItemDB) -> Self {
+            OrderItem {
+                pet: item.pet(),
+                unit_price: item.unit_price(),
+                id: item.id as u64,
+                pet_id: item.pet_id.map(|pet_id| pet_id as u64),
+                quantity: item.quantity as u64,
+            }
+        }
+    }
 
-        Ok(res)
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct OrderDiscountDB {
+        pub id: i64,
+        pub order_id: i64,
+        pub promotion_id: Option<i64>,
+        /// The coupon code redeemed, `None` for promotions without one.
+        pub code: Option<String>,
+        pub amount: i64,
+        pub free_shipping: bool,
     }
 
-    #[tracing::instrument(skip(state))]
-    pub async fn delete(state: AppState, id: u64) -> Result<()> {
-        let _ = sqlx::query("delete from orders where id = $1")
-            .bind(id as i64)
-            .execute(&state.db.clone())
-            .await?;
+    impl From<OrderDiscountDB> for OrderDiscount {
+        fn from(discount: OrderDiscountDB) -> Self {
+            OrderDiscount {
+                promotion_id: discount.promotion_id.map(|id| id as u64),
+                code: discount.code,
+                amount: discount.amount as u64,
+                free_shipping: discount.free_shipping,
+            }
+        }
+    }
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct OrderTransitionDB {
+        pub id: i64,
+        pub order_id: i64,
+        pub from_status: OrderStatus,
+        pub to_status: OrderStatus,
+        pub actor_id: i64,
+        pub changed_at: DateTime<Utc>,
+    }
+
+    impl From<OrderTransitionDB> for OrderTransition {
+        fn from(transition: OrderTransitionDB) -> Self {
+            OrderTransition {
+                from: transition.from_status,
+                to: transition.to_status,
+                actor_id: transition.actor_id as u64,
+                changed_at: transition.changed_at,
+            }
+        }
+    }
+
+    #[async_trait]
+    pub trait OrderRepository: Debug + Send + Sync {
+        /// Inserts an order with its items and discounts ignoring their ids and
+        /// returns it with the ids assigned by the database.
+        async fn create(&self, order: OrderDB) -> Result<OrderDB>;
+        async fn get(&self, order_id: u64) -> Result<Option<OrderDB>>;
+        async fn list(&self, user_id: u64) -> Result<Vec<OrderDB>>;
+        /// Stores the order along with the pets, prices and quantities of its items,
+        /// which are matched by id, items are never added or removed. Returns
+        /// `None` when there is no order with `o.id`.
+        async fn update(&self, o: OrderDB) -> Result<Option<OrderDB>>;
+        /// Moves the order from `from` to `to` and records who did it. Returns
+        /// `None` without touching anything when the order is no longer in `from`.
+        async fn transition(
+            &self,
+            order_id: u64,
+            from: OrderStatus,
+            to: OrderStatus,
+            actor_id: u64,
+        ) -> Result<Option<OrderDB>>;
+        async fn history(&self, order_id: u64) -> Result<Vec<OrderTransitionDB>>;
+        /// Orders waiting for a pet to be allocated, oldest first.
+        async fn back_orders(&self) -> Result<Vec<OrderDB>>;
+        /// `Awaiting` orders, and back-orders holding pets, whose pets were
+        /// reserved before `reserved_before`.
+        async fn expired(&self, reserved_before: DateTime<Utc>) -> Result<Vec<OrderDB>>;
+    }
+
+    /// Fills in the `items` and `discounts` of `orders`.
+    async fn load_related(db: &Sql, orders: &mut [OrderDB]) -> Result<()> {
+        if orders.is_empty() {
+            return Ok(());
+        }
+        let select_items = format!(
+            "select *
+            from order_items i
+            where i.order_id in ({})
+            order by i.id",
+            in_params(orders.len())
+        );
+        let select_discounts = format!(
+            "select *
+            from order_discounts d
+            where d.order_id in ({})
+            order by d.id",
+            in_params(orders.len())
+        );
+
+        let (items, discounts): (Vec<OrderItemDB>, Vec<OrderDiscountDB>) = on_conn!(db, |conn| {
+            let mut query = sqlx::query_as(&select_items);
+            let mut discounts = sqlx::query_as(&select_discounts);
+            for order in orders.iter() {
+                query = query.bind(order.id);
+                discounts = discounts.bind(order.id);
+            }
+            (
+                query.fetch_all(&mut *conn).await?,
+                discounts.fetch_all(&mut *conn).await?,
+            )
+        });
+        for item in items {
+            if let Some(order) = orders.iter_mut().find(|order| order.id == item.order_id) {
+                order.items.push(item);
+            }
+        }
+        for discount in discounts {
+            if let Some(order) = orders
+                .iter_mut()
+                .find(|order| order.id == discount.order_id)
+            {
+                order.discounts.push(discount);
+            }
+        }
         Ok(())
     }
 
-    #[tracing::instrument(skip(state))]
-    pub async fn update(state: AppState, o: OrderDB) -> Result<()> {
-        let _ = sqlx::query::<Postgres>(
-            "insert into orders as o(id, pet_id, user_id, quantity, ship_date, status) 
-            values ($1, $2, $3, $4, $5, $6)
-            on conflict(id) 
-            do update set 
-                pet_id = excluded.pet_id, 
-                user_id = excluded.user_id, 
-                quantity = excluded.quantity, 
-                ship_date = excluded.ship_date, 
-                status = excluded.status;
-            ",
-        )
-        .bind(o.id)
-        .bind(o.pet_id)
-        .bind(o.user_id)
-        .bind(o.quantity)
-        .bind(o.ship_date)
-        .bind(o.status)
-        .execute(&state.db.clone())
-        .await?;
+    #[async_trait]
+    impl OrderRepository for Sql {
+        #[tracing::instrument(skip(self))]
+        async fn create(&self, order: OrderDB) -> Result<OrderDB> {
+            let res: OrderDB = on_conn!(self, |conn| {
+                let mut tx = conn.begin().await?;
+                let mut created: OrderDB = sqlx::query_as(
+                    "insert into orders (user_id, ship_date, status, reserved_at, tax_rate)
+                    values ($1, $2, $3, $4, $5)
+                    returning *;",
+                )
+                .bind(order.user_id)
+                .bind(order.ship_date)
+                .bind(order.status.clone())
+                .bind(order.reserved_at)
+                .bind(order.tax_rate)
+                .fetch_one(&mut *tx)
+                .await?;
+
+                for item in &order.items {
+                    let item: OrderItemDB = sqlx::query_as(
+                        "insert into order_items
+                            (order_id, pet_id, quantity, pet_category, pet_size,
+                            unit_price, currency)
+                        values ($1, $2, $3, $4, $5, $6, $7)
+                        returning *;",
+                    )
+                    .bind(created.id)
+                    .bind(item.pet_id)
+                    .bind(item.quantity)
+                    .bind(item.pet_category.clone())
+                    .bind(item.pet_size.clone())
+                    .bind(item.unit_price)
+                    .bind(item.currency.clone())
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    created.items.push(item);
+                }
+                for discount in &order.discounts {
+                    let discount: OrderDiscountDB = sqlx::query_as(
+                        "insert into order_discounts
+                            (order_id, promotion_id, code, amount, free_shipping)
+                        values ($1, $2, $3, $4, $5)
+                        returning *;",
+                    )
+                    .bind(created.id)
+                    .bind(discount.promotion_id)
+                    .bind(discount.code.clone())
+                    .bind(discount.amount)
+                    .bind(discount.free_shipping)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    created.discounts.push(discount);
+                }
+                tx.commit().await?;
+                created
+            });
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, order_id: u64) -> Result<Option<OrderDB>> {
+            let mut res: Option<OrderDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from orders o
+                    where o.id = $1",
+                )
+                .bind(order_id as i64)
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+            load_related(self, res.as_mut_slice()).await?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn list(&self, user_id: u64) -> Result<Vec<OrderDB>> {
+            let mut res: Vec<OrderDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from orders o
+                    where o.user_id = $1
+                    order by o.id desc",
+                )
+                .bind(user_id as i64)
+                .fetch_all(&mut *conn)
+                .await
+            })?;
+            load_related(self, &mut res).await?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update(&self, o: OrderDB) -> Result<Option<OrderDB>> {
+            let mut res: Option<OrderDB> = on_conn!(self, |conn| {
+                let mut tx = conn.begin().await?;
+                let res: Option<OrderDB> = sqlx::query_as(
+                    "update orders set
+                        user_id = $2,
+                        ship_date = $3,
+                        status = $4,
+                        reserved_at = $5,
+                        tax_rate = $6
+                    where id = $1
+                    returning *;",
+                )
+                .bind(o.id)
+                .bind(o.user_id)
+                .bind(o.ship_date)
+                .bind(o.status.clone())
+                .bind(o.reserved_at)
+                .bind(o.tax_rate)
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                if res.is_some() {
+                    for item in &o.items {
+                        sqlx::query(
+                            "update order_items
+                            set pet_id = $3, quantity = $4, unit_price = $5, currency = $6
+                            where id = $1 and order_id = $2;",
+                        )
+                        .bind(item.id)
+                        .bind(o.id)
+                        .bind(item.pet_id)
+                        .bind(item.quantity)
+                        .bind(item.unit_price)
+                        .bind(item.currency.clone())
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                }
+                tx.commit().await?;
+                res
+            });
+            load_related(self, res.as_mut_slice()).await?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn transition(
+            &self,
+            order_id: u64,
+            from: OrderStatus,
+            to: OrderStatus,
+            actor_id: u64,
+        ) -> Result<Option<OrderDB>> {
+            let mut res: Option<OrderDB> = on_conn!(self, |conn| {
+                let mut tx = conn.begin().await?;
+                let res: Option<OrderDB> = sqlx::query_as(
+                    "update orders set status = $3
+                    where id = $1 and status = $2
+                    returning *;",
+                )
+                .bind(order_id as i64)
+                .bind(from.clone())
+                .bind(to.clone())
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                if res.is_some() {
+                    sqlx::query(
+                        "insert into order_status_history (order_id, from_status, to_status, actor_id)
+                        values ($1, $2, $3, $4);",
+                    )
+                    .bind(order_id as i64)
+                    .bind(from)
+                    .bind(to)
+                    .bind(actor_id as i64)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                tx.commit().await?;
+                res
+            });
+            load_related(self, res.as_mut_slice()).await?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn history(&self, order_id: u64) -> Result<Vec<OrderTransitionDB>> {
+            let res: Vec<OrderTransitionDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from order_status_history h
+                    where h.order_id = $1
+                    order by h.changed_at, h.id",
+                )
+                .bind(order_id as i64)
+                .fetch_all(&mut *conn)
+                .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn back_orders(&self) -> Result<Vec<OrderDB>> {
+            let mut res: Vec<OrderDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from orders o
+                    where o.status = $1
+                    order by o.id",
+                )
+                .bind(OrderStatus::BackOrdered)
+                .fetch_all(&mut *conn)
+                .await
+            })?;
+            load_related(self, &mut res).await?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn expired(&self, reserved_before: DateTime<Utc>) -> Result<Vec<OrderDB>> {
+            let mut res: Vec<OrderDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from orders o
+                    where o.status in ($1, $2) and o.reserved_at < $3
+                    order by o.id",
+                )
+                .bind(OrderStatus::Awaiting)
+                .bind(OrderStatus::BackOrdered)
+                .bind(reserved_before)
+                .fetch_all(&mut *conn)
+                .await
+            })?;
+            load_related(self, &mut res).await?;
+
+            Ok(res)
+        }
+    }
 
+    /// The foreign keys `orders`, `order_items` and `order_discounts` have in
+    /// the database.
+    fn check_references(tables: &Tables, order: &OrderDB) -> Result<()> {
+        if order
+            .items
+            .iter()
+            .filter_map(|item| item.pet_id)
+            .any(|pet_id| !tables.pets.contains_key(&pet_id))
+        {
+            return Err(foreign_key_violation("order_items_pet_id_fkey"));
+        }
+        if order
+            .discounts
+            .iter()
+            .filter_map(|discount| discount.promotion_id)
+            .any(|promotion_id| !tables.promotions.contains_key(&promotion_id))
+        {
+            return Err(foreign_key_violation("order_discounts_promotion_id_fkey"));
+        }
+        if !tables.users.contains_key(&order.user_id) {
+            return Err(foreign_key_violation("orders_user_id_fkey"));
+        }
         Ok(())
     }
+
+    /// `order` with the items and discounts `order_items` and
+    /// `order_discounts` hold for it.
+    fn with_related(tables: &Tables, order: &OrderDB) -> OrderDB {
+        OrderDB {
+            items: tables
+                .order_items
+                .values()
+                .filter(|item| item.order_id == order.id)
+                .cloned()
+                .collect(),
+            discounts: tables
+                .order_discounts
+                .values()
+                .filter(|discount| discount.order_id == order.id)
+                .cloned()
+                .collect(),
+            ..order.clone()
+        }
+    }
+
+    #[async_trait]
+    impl OrderRepository for MemoryDb {
+        async fn create(&self, order: OrderDB) -> Result<OrderDB> {
+            let mut tables = self.lock();
+            check_references(&tables, &order)?;
+            let id = tables.next_id();
+            for item in order.items {
+                let item = OrderItemDB {
+                    id: tables.next_id(),
+                    order_id: id,
+                    ..item
+                };
+                tables.order_items.insert(item.id, item);
+            }
+            for discount in order.discounts {
+                let discount = OrderDiscountDB {
+                    id: tables.next_id(),
+                    order_id: id,
+                    ..discount
+                };
+                tables.order_discounts.insert(discount.id, discount);
+            }
+            let order = OrderDB {
+                id,
+                items: Vec::new(),
+                discounts: Vec::new(),
+                ..order
+            };
+            tables.orders.insert(id, order.clone());
+            Ok(with_related(&tables, &order))
+        }
+
+        async fn get(&self, order_id: u64) -> Result<Option<OrderDB>> {
+            let tables = self.lock();
+            Ok(tables
+                .orders
+                .get(&(order_id as i64))
+                .map(|order| with_related(&tables, order)))
+        }
+
+        async fn list(&self, user_id: u64) -> Result<Vec<OrderDB>> {
+            let tables = self.lock();
+            Ok(tables
+                .orders
+                .values()
+                .rev()
+                .filter(|o| o.user_id == user_id as i64)
+                .map(|o| with_related(&tables, o))
+                .collect())
+        }
+
+        async fn update(&self, o: OrderDB) -> Result<Option<OrderDB>> {
+            let mut tables = self.lock();
+            if !tables.orders.contains_key(&o.id) {
+                return Ok(None);
+            }
+            check_references(&tables, &o)?;
+            for item in &o.items {
+                let stored = tables
+                    .order_items
+                    .get_mut(&item.id)
+                    .filter(|stored| stored.order_id == o.id);
+                if let Some(stored) = stored {
+                    stored.pet_id = item.pet_id;
+                    stored.quantity = item.quantity;
+                    stored.unit_price = item.unit_price;
+                    stored.currency = item.currency.clone();
+                }
+            }
+            let order = OrderDB {
+                items: Vec::new(),
+                discounts: Vec::new(),
+                ..o
+            };
+            tables.orders.insert(order.id, order.clone());
+            Ok(Some(with_related(&tables, &order)))
+        }
+
+        async fn transition(
+            &self,
+            order_id: u64,
+            from: OrderStatus,
+            to: OrderStatus,
+            actor_id: u64,
+        ) -> Result<Option<OrderDB>> {
+            let mut tables = self.lock();
+            let order_id = order_id as i64;
+            let Some(order) = tables
+                .orders
+                .get_mut(&order_id)
+                .filter(|order| order.status == from)
+            else {
+                return Ok(None);
+            };
+            order.status = to.clone();
+            let order = order.clone();
+            let id = tables.next_id();
+            tables.order_status_history.insert(
+                id,
+                OrderTransitionDB {
+                    id,
+                    order_id,
+                    from_status: from,
+                    to_status: to,
+                    actor_id: actor_id as i64,
+                    changed_at: Utc::now(),
+                },
+            );
+            Ok(Some(with_related(&tables, &order)))
+        }
+
+        async fn history(&self, order_id: u64) -> Result<Vec<OrderTransitionDB>> {
+            Ok(self
+                .lock()
+                .order_status_history
+                .values()
+                .filter(|t| t.order_id == order_id as i64)
+                .cloned()
+                .collect())
+        }
+
+        async fn back_orders(&self) -> Result<Vec<OrderDB>> {
+            let tables = self.lock();
+            Ok(tables
+                .orders
+                .values()
+                .filter(|o| o.status == OrderStatus::BackOrdered)
+                .map(|o| with_related(&tables, o))
+                .collect())
+        }
+
+        async fn expired(&self, reserved_before: DateTime<Utc>) -> Result<Vec<OrderDB>> {
+            let tables = self.lock();
+            Ok(tables
+                .orders
+                .values()
+                .filter(|o| matches!(o.status, OrderStatus::Awaiting | OrderStatus::BackOrdered))
+                .filter(|o| o.reserved_at.is_some_and(|at| at < reserved_before))
+                .map(|o| with_related(&tables, o))
+                .collect())
+        }
+    }
 }
 
 pub mod api {
 
     use axum::{
-        extract::State,
-        routing::{delete, get, post, put},
+        middleware,
+        routing::{get, post},
         Router,
     };
-    use storage::OrderStorage;
 
-    use crate::AppState;
+    use crate::{
+        auth::{self, Permission},
+        AppState,
+    };
 
     use super::service;
-    use super::storage;
 
     pub(crate) fn create_router() -> Router<AppState> {
+        let fulfil_orders = middleware::from_fn(auth::require(Permission::FulfilOrders));
+
         Router::new()
             .route("/", post(service::create_order))
             .route(
@@ -259,212 +1620,1147 @@ pub mod api {
                     .delete(service::delete)
                     .post(service::update_order),
             )
+            .route(
+                "/:order_id/approve",
+                post(service::approve).route_layer(fulfil_orders.clone()),
+            )
+            .route(
+                "/:order_id/deliver",
+                post(service::deliver).route_layer(fulfil_orders),
+            )
+            .route("/:order_id/cancel", post(service::cancel))
+            .route("/:order_id/history", get(service::history))
             .route("/all/:user_id", get(service::list_orders))
+            .route_layer(middleware::from_fn(auth::require(Permission::PlaceOrders)))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use axum::extract::State;
 
     use crate::{
-        persistence::{Storage, StorageConfig, StorageIml},
-        AppStateInner,
+        pet::{storage::PetDB, PetCategory, PetStatus},
+        testing, AppState,
     };
-    use axum::{extract::State, serve::Serve, Router};
-
-    use crate::{config::AppConfig, AppState};
     use anyhow::Result;
 
-    async fn fixture() -> Result<State<AppState>> {
-        let config = AppConfig::load_config()?;
+    fn fixture() -> State<AppState> {
+        State(AppState::in_memory())
+    }
 
-        let storage_conf = StorageConfig {
-            db_path: config.db(),
+    /// Inserts a customer and a cat for orders to reference, returns their ids.
+    async fn seed(state: &AppState) -> Result<(i64, i64)> {
+        let cat = PetDB {
+            id: 0,
+            category: Some(PetCategory::Feline),
+            status: PetStatus::Available,
+            size: None,
+            price: None,
+            currency: None,
+            photo_urls: vec!["https://example.com/cat.png".to_string()],
+            tags: Vec::new(),
+            thumbnails: Vec::new(),
         };
-        let storage = StorageIml.conn(storage_conf).await?;
-        StorageIml.migrate(storage.clone()).await?;
-
-        Ok(State(AppState {
-            inner: Arc::new(AppStateInner {
-                db: storage,
-                version: "0.0.1".to_string(),
-            }),
-        }))
+        testing::seed(state, cat).await
     }
 
-    async fn create_server(
-        routes: Router<AppState>,
-        state: AppState,
-    ) -> Result<Serve<Router, Router>> {
-        let listener = tokio::net::TcpListener::bind("127.0.0.1:9009").await?;
-        let router = routes.with_state(state.clone());
-        let server = axum::serve(listener, router);
-        Ok(server)
-    }
-    // mod api {
-    //     use std::{net::IpAddr, time::Duration};
-
-    //     use axum::Router;
-    //     use reqwest::{Response, StatusCode};
-    //     use serde_json::json;
-
-    //     use crate::{
-    //         orders::{api, tests::create_server, Order, OrderStatus},
-    //         AppState,
-    //     };
-
-    //     use super::fixture;
-
-    //     #[tokio::test]
-    //     async fn create_order_test() -> anyhow::Result<()> {
-    //         let state = fixture().await?;
-    //         let router: Router<AppState> = api::create_router().with_state(state.0.clone());
-    //         let order: Order = Order {
-    //             id: 0,
-    //             user_id: 0,
-    //             pet_id: 0,
-    //             quantity: 0,
-    //             ship_date: None,
-    //             status: OrderStatus::Awaiting,
-    //         };
-
-    //         let req_body = serde_json::to_string(&order)?;
-
-    //         let _ = create_server(router, state.0.clone()).await?;
-    //         let client = reqwest::Client::builder()
-    //             .connection_verbose(true)
-    //             .connect_timeout(Duration::from_millis(300))
-    //             .build()?;
-            
-
-    //         // .get("http://localhost:9009/1")
-    //         // .send()
-    //         // .await?;
-
-    //         // let res = &client.get("https://hyper.rs").send().await?;
-    //         // let res = res.clone();
-
-    //         // assert!(&res.status().is_success());
-
-    //         // assert!(res.is_ok());
-    //         // assert!(res.is_ok());
-    //         // let res = reqwest::Client::new()
-    //         // .get("http://localhost:9009/1")
-    //         // .send()
-    //         // .await?;
-
-    //         // let res = reqwest::Client::new()
-    //         //     .post("http://127.0.0.1:9003/order/create")
-    //         //     .body(req_body)
-    //         //     .send()
-    //         //     .await?;
-
-    //         // assert_eq!(&res.status().as_str(), &"200");
-
-    //         // let body = serde_json::from_str::<Order>(&res.text().await?)?;
-
-    //         // assert_eq!(body, order);
-
-    //         state.0.shutdown().await?;
-    //         Ok(())
-    //     }
-    // }
-    mod storage {
-
-        use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+    mod api {
+        use axum::http::StatusCode;
+        use serde_json::json;
 
-        use crate::orders::{
-            storage::{self, OrderDB},
-            tests::fixture,
-            OrderStatus,
+        use crate::{
+            auth::Role,
+            orders::{api, tests::seed},
+            testing::{app, register, request, send},
+            AppState,
         };
 
         #[tokio::test]
-        async fn get_order() -> anyhow::Result<()> {
-            let state = fixture().await?;
-            // let state = Arc::new(state);
+        async fn require_permissions() -> anyhow::Result<()> {
+            let state = AppState::in_memory();
+            let (customer_id, pet_id) = seed(&state).await?;
+            let staff_id = register(&state, Role::Staff).await?;
+            let orders = || app(&state, "/orders", api::create_router());
+            let order = json!({"pet_id": pet_id, "quantity": 1});
+
+            let anonymous = request(&state, "POST", "/orders", None, Some(order.clone()))?;
+            assert_eq!(
+                StatusCode::UNAUTHORIZED,
+                send(orders(), anonymous).await?.status()
+            );
+            // the token outlived its account
+            let gone = request(
+                &state,
+                "POST",
+                "/orders",
+                Some(i64::MAX),
+                Some(order.clone()),
+            )?;
+            assert_eq!(
+                StatusCode::UNAUTHORIZED,
+                send(orders(), gone).await?.status()
+            );
+            let placed = request(&state, "POST", "/orders", Some(customer_id), Some(order))?;
+            assert_eq!(StatusCode::CREATED, send(orders(), placed).await?.status());
+
+            let order_id = state.orders.list(customer_id as u64).await?[0].id;
+            let approve = format!("/orders/{order_id}/approve");
+            let by_customer = request(&state, "POST", &approve, Some(customer_id), None)?;
+            assert_eq!(
+                StatusCode::FORBIDDEN,
+                send(orders(), by_customer).await?.status()
+            );
+            let by_staff = request(&state, "POST", &approve, Some(staff_id), None)?;
+            assert_eq!(StatusCode::OK, send(orders(), by_staff).await?.status());
+            Ok(())
+        }
+    }
 
-            let res = storage::get(state.0.clone(), 0).await?;
+    mod service {
+        use axum::{body::to_bytes, extract::Path, http::StatusCode, response::IntoResponse};
+        use chrono::{Duration, Utc};
+        use serde_json::{json, Value};
+
+        use crate::{
+            orders::{
+                fill_back_orders, service,
+                storage::OrderDB,
+                tests::{fixture, seed},
+                Order, OrderPet, OrderStatus,
+            },
+            pet::{storage::PetDB, PetCategory, PetSize, PetStatus},
+            promotion::{storage::PromotionDB, Promotion},
+            testing::customer,
+            validation::Valid,
+        };
 
-            assert_eq!(None, res);
+        #[tokio::test]
+        async fn place_and_cancel_order() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, pet_id) = seed(&state.0).await?;
+            let order = Order {
+                id: 0,
+                user_id: 0,
+                items: Vec::new(),
+                pet_id: Some(pet_id as u64),
+                pet: None,
+                quantity: Some(1),
+                total_quantity: 0,
+                coupon: None,
+                discounts: Vec::new(),
+                currency: None,
+                subtotal: 0,
+                discount: 0,
+                tax: 0,
+                total: 0,
+                ship_date: None,
+                status: Some(OrderStatus::Delivered),
+            };
+
+            let created =
+                service::create_order(state.clone(), customer(user_id), Valid(order.clone()))
+                    .await
+                    .into_response();
+            let order_id = state.orders.list(user_id as u64).await?[0].id as u64;
+            assert_eq!(StatusCode::CREATED, created.status());
+            assert_eq!(
+                PetStatus::Pending,
+                state.pets.get(pet_id as u64).await?.unwrap().status
+            );
+            assert_eq!(
+                format!("/orders/{order_id}"),
+                created.headers()["location"].to_str()?
+            );
+            assert_eq!(
+                OrderStatus::Awaiting,
+                state.orders.get(order_id).await?.unwrap().status
+            );
 
-            state.0.shutdown().await?;
+            let taken =
+                service::create_order(state.clone(), customer(user_id), Valid(order.clone()))
+                    .await
+                    .into_response();
+            assert_eq!(StatusCode::CONFLICT, taken.status());
+
+            // editing the pet does not put it back on sale
+            let reserved = state.pets.get(pet_id as u64).await?.unwrap();
+            state
+                .pets
+                .update(PetDB {
+                    status: PetStatus::Available,
+                    ..reserved
+                })
+                .await?;
+            let taken =
+                service::create_order(state.clone(), customer(user_id), Valid(order.clone()))
+                    .await
+                    .into_response();
+            assert_eq!(StatusCode::CONFLICT, taken.status());
+
+            let foreign = service::cancel(state.clone(), customer(user_id + 1), Path(order_id))
+                .await
+                .into_response();
+            let cancelled = service::cancel(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+            let again = service::cancel(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+
+            assert_eq!(StatusCode::FORBIDDEN, foreign.status());
+            assert_eq!(StatusCode::OK, cancelled.status());
+            assert_eq!(StatusCode::CONFLICT, again.status());
+            assert_eq!(1, state.orders.history(order_id).await?.len());
+            assert_eq!(
+                PetStatus::Available,
+                state.pets.get(pet_id as u64).await?.unwrap().status
+            );
 
+            service::create_order(state.clone(), customer(user_id), Valid(order))
+                .await
+                .into_response();
+            let order_id = state.orders.list(user_id as u64).await?[0].id as u64;
+            let deleted = service::delete(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+            let again = service::delete(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+
+            assert_eq!(StatusCode::OK, deleted.status());
+            assert_eq!(StatusCode::OK, again.status());
+            assert_eq!(
+                OrderStatus::Cancelled,
+                state.orders.get(order_id).await?.unwrap().status
+            );
+            assert_eq!(1, state.orders.history(order_id).await?.len());
+            assert_eq!(
+                PetStatus::Available,
+                state.pets.get(pet_id as u64).await?.unwrap().status
+            );
             Ok(())
         }
+
         #[tokio::test]
-        async fn insert_order() -> anyhow::Result<()> {
-            let state = fixture().await?;
+        async fn deliver_and_expire_reservations() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, pet_id) = seed(&state.0).await?;
+            let order = Order {
+                id: 0,
+                user_id: 0,
+                items: Vec::new(),
+                pet_id: Some(pet_id as u64),
+                pet: None,
+                quantity: Some(1),
+                total_quantity: 0,
+                coupon: None,
+                discounts: Vec::new(),
+                currency: None,
+                subtotal: 0,
+                discount: 0,
+                tax: 0,
+                total: 0,
+                ship_date: None,
+                status: Some(OrderStatus::Awaiting),
+            };
+            let place = |order: Order| {
+                service::create_order(state.clone(), customer(user_id), Valid(order))
+            };
 
-            let d = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
-            let t = NaiveTime::from_hms_milli_opt(23, 22, 0, 0).unwrap();
-            let test_order = OrderDB {
-                id: 1,
-                pet_id: 0,
+            place(order.clone()).await.into_response();
+            let order_id = state.orders.list(user_id as u64).await?[0].id as u64;
+            service::approve(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+            let delivered = service::deliver(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::OK, delivered.status());
+            assert_eq!(
+                PetStatus::Sold,
+                state.pets.get(pet_id as u64).await?.unwrap().status
+            );
+            let sold = place(order.clone()).await.into_response();
+            assert_eq!(StatusCode::CONFLICT, sold.status());
+
+            let (_, other_pet) = seed(&state.0).await?;
+            place(Order {
+                pet_id: Some(other_pet as u64),
+                ..order.clone()
+            })
+            .await
+            .into_response();
+            let order_id = state.orders.list(user_id as u64).await?[0].id as u64;
+
+            let hour_ago = Utc::now() - Duration::hours(1);
+            assert_eq!(0, service::expire_reservations(&state, hour_ago).await?);
+            let later = Utc::now() + Duration::seconds(1);
+            assert_eq!(1, service::expire_reservations(&state, later).await?);
+            assert_eq!(
+                OrderStatus::Cancelled,
+                state.orders.get(order_id).await?.unwrap().status
+            );
+            assert_eq!(
+                PetStatus::Available,
+                state.pets.get(other_pet as u64).await?.unwrap().status
+            );
+            assert_eq!(0, state.orders.history(order_id).await?[0].actor_id);
+
+            // so do back-orders holding some of their pets
+            let house_cat = state
+                .pets
+                .create(PetDB {
+                    id: 0,
+                    category: Some(PetCategory::Feline),
+                    status: PetStatus::Available,
+                    size: Some(PetSize::House),
+                    price: None,
+                    currency: None,
+                    photo_urls: Vec::new(),
+                    tags: Vec::new(),
+                    thumbnails: Vec::new(),
+                })
+                .await?;
+            place(Order {
+                pet_id: None,
+                pet: Some(OrderPet {
+                    category: PetCategory::Feline,
+                    pet_size: PetSize::House,
+                }),
+                quantity: Some(2),
+                ..order
+            })
+            .await
+            .into_response();
+            let order_id = state.orders.list(user_id as u64).await?[0].id as u64;
+            assert_eq!(
+                OrderStatus::BackOrdered,
+                state.orders.get(order_id).await?.unwrap().status
+            );
+            assert_eq!(0, service::expire_reservations(&state, hour_ago).await?);
+            let later = Utc::now() + Duration::seconds(1);
+            assert_eq!(1, service::expire_reservations(&state, later).await?);
+            assert_eq!(
+                PetStatus::Available,
+                state.pets.get(house_cat as u64).await?.unwrap().status
+            );
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn reject_order_for_unknown_pet() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, _) = seed(&state.0).await?;
+            let order = Order {
+                id: 0,
                 user_id: 0,
-                quantity: 32,
-                ship_date: Some(NaiveDateTime::new(d, t).and_utc()),
-                status: OrderStatus::Approved,
+                items: Vec::new(),
+                pet_id: Some(404),
+                pet: None,
+                quantity: Some(1),
+                total_quantity: 0,
+                coupon: None,
+                discounts: Vec::new(),
+                currency: None,
+                subtotal: 0,
+                discount: 0,
+                tax: 0,
+                total: 0,
+                ship_date: None,
+                status: Some(OrderStatus::Awaiting),
             };
-            storage::create(state.0.clone(), test_order.clone()).await?;
-            // orders_storage.create(test_order.clone()).await?;
-            let get_res = storage::get(state.0.clone(), 1).await?;
 
-            assert_eq!(test_order.id, get_res.unwrap().id);
+            let res = service::create_order(state.clone(), customer(user_id), Valid(order))
+                .await
+                .into_response();
 
-            storage::delete(state.0.clone(), 1).await?;
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            Ok(())
+        }
 
-            state.0.shutdown().await?;
+        #[tokio::test]
+        async fn order_several_items() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, cat) = seed(&state.0).await?;
+            let dog = || PetDB {
+                id: 0,
+                category: Some(PetCategory::Canine),
+                status: PetStatus::Available,
+                size: Some(PetSize::Flat),
+                price: None,
+                currency: None,
+                photo_urls: Vec::new(),
+                tags: Vec::new(),
+                thumbnails: Vec::new(),
+            };
+            let rex = state.pets.create(dog()).await?;
+            let fido = state.pets.create(dog()).await?;
+            let order: Order = serde_json::from_value(json!({
+                "items": [
+                    {"pet_id": cat, "quantity": 1},
+                    {"pet": {"category": "Canine", "pet_size": "Flat"}, "quantity": 2},
+                ],
+            }))?;
+
+            let res = service::create_order(state.clone(), customer(user_id), Valid(order))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let body: Value =
+                serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+            assert_eq!(json!(3), body["total_quantity"]);
+            assert_eq!(json!(rex), body["items"][1]["pet_id"]);
+            assert_eq!(json!(fido), body["items"][2]["pet_id"]);
+            assert_eq!(json!(1), body["items"][2]["quantity"], "one pet per item");
+            assert_eq!(None, body.get("pet_id"), "only filled in for a single item");
+            for pet_id in [cat, rex, fido] {
+                assert_eq!(
+                    PetStatus::Pending,
+                    state.pets.get(pet_id as u64).await?.unwrap().status
+                );
+            }
 
+            let order_id = body["id"].as_u64().unwrap();
+            service::cancel(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+            for pet_id in [cat, rex, fido] {
+                assert_eq!(
+                    PetStatus::Available,
+                    state.pets.get(pet_id as u64).await?.unwrap().status
+                );
+            }
             Ok(())
         }
+
         #[tokio::test]
-        async fn update_order() -> anyhow::Result<()> {
-            let state = fixture().await?;
-            let d = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
-            let t = NaiveTime::from_hms_milli_opt(23, 22, 0, 0).unwrap();
+        async fn price_orders() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, _) = seed(&state.0).await?;
+            let priced = |price: i64, currency: &str| PetDB {
+                id: 0,
+                category: Some(PetCategory::Birds),
+                status: PetStatus::Available,
+                size: Some(PetSize::House),
+                price: Some(price),
+                currency: Some(currency.to_string()),
+                photo_urls: Vec::new(),
+                tags: Vec::new(),
+                thumbnails: Vec::new(),
+            };
+            let parrot = state.pets.create(priced(1250, "EUR")).await?;
+            state.pets.create(priced(3000, "EUR")).await?;
+            state.pets.create(priced(3000, "EUR")).await?;
+            let finch = state.pets.create(priced(900, "USD")).await?;
+            let place = |body: Value| {
+                let order: Order = serde_json::from_value(body).unwrap();
+                service::create_order(state.clone(), customer(user_id), Valid(order))
+            };
+
+            let res = place(json!({
+                "items": [
+                    {"pet_id": parrot, "quantity": 1},
+                    {"pet": {"category": "Birds", "pet_size": "House"}, "quantity": 2},
+                ],
+            }))
+            .await
+            .into_response();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let body: Value =
+                serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+            assert_eq!(
+                json!({"amount": 1250, "currency": "EUR"}),
+                body["items"][0]["unit_price"]
+            );
+            // the USD finch is older, but does not fit an order in EUR
+            assert_ne!(json!(finch), body["items"][1]["pet_id"]);
+            assert_eq!(json!("EUR"), body["currency"]);
+            assert_eq!(json!(7250), body["subtotal"]);
+            assert_eq!(json!(0), body["tax"]);
+            assert_eq!(json!(7250), body["total"]);
+
+            // later price changes leave the order alone, the tax rate is kept too
+            let mut repriced = state.pets.get(parrot as u64).await?.unwrap();
+            repriced.price = Some(5000);
+            state.pets.update(repriced).await?;
+            let order_id = body["id"].as_u64().unwrap();
+            let stored = state.orders.get(order_id).await?.unwrap();
+            assert_eq!(Some(1250), stored.items[0].unit_price);
+            let totals = service::totals(&OrderDB {
+                tax_rate: 1900,
+                ..stored
+            });
+            assert_eq!(1378, totals.tax, "19% of 72.50 rounded half up");
+            assert_eq!(8628, totals.total);
+
+            // one currency per order, nothing stays reserved by a rejected one
+            let canary = state.pets.create(priced(800, "EUR")).await?;
+            let res = place(json!({
+                "items": [
+                    {"pet_id": finch, "quantity": 1},
+                    {"pet_id": canary, "quantity": 1},
+                ],
+            }))
+            .await
+            .into_response();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+            assert_eq!(
+                PetStatus::Available,
+                state.pets.get(finch as u64).await?.unwrap().status
+            );
 
-            let test_order = OrderDB {
-                id: 1,
-                pet_id: 0,
+            // nor is a pet in another currency allocated
+            let res = place(json!({
+                "items": [
+                    {"pet_id": finch, "quantity": 1},
+                    {"pet": {"category": "Birds", "pet_size": "House"}, "quantity": 1},
+                ],
+            }))
+            .await
+            .into_response();
+            let body: Value =
+                serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+            assert_eq!(json!("backordered"), body["status"]);
+            assert_eq!(json!(900), body["total"]);
+
+            // however many pets in other currencies come first
+            for _ in 0..10 {
+                state.pets.create(priced(700, "EUR")).await?;
+            }
+            let robin = state.pets.create(priced(600, "USD")).await?;
+            fill_back_orders(&state.0, 7).await?;
+            let order_id = body["id"].as_u64().unwrap();
+            let filled = state.orders.get(order_id).await?.unwrap();
+            assert_eq!(OrderStatus::Awaiting, filled.status);
+            assert_eq!(Some(robin), filled.items[1].pet_id);
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn apply_promotions() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, _) = seed(&state.0).await?;
+            let hamster = || PetDB {
+                id: 0,
+                category: Some(PetCategory::Rodents),
+                status: PetStatus::Available,
+                size: Some(PetSize::Terraium),
+                price: Some(2000),
+                currency: Some("EUR".to_string()),
+                photo_urls: Vec::new(),
+                tags: Vec::new(),
+                thumbnails: Vec::new(),
+            };
+            for promotion in [
+                json!({
+                    "rule": {"type": "category", "category": "Rodents"},
+                    "discount": {"type": "percent", "percent": 10},
+                }),
+                json!({
+                    "code": "SPRING",
+                    "rule": {"type": "order_total", "min_subtotal": 3000},
+                    "discount": {"type": "amount", "amount": 500},
+                    "currency": "EUR",
+                    "max_uses": 1,
+                }),
+            ] {
+                let promotion: Promotion = serde_json::from_value(promotion)?;
+                state
+                    .promotions
+                    .create(PromotionDB::from(promotion))
+                    .await?;
+            }
+            let place = |coupon: &str| {
+                let order: Order = serde_json::from_value(json!({
+                    "pet": {"category": "Rodents", "pet_size": "Terraium"},
+                    "quantity": 2,
+                    "coupon": coupon,
+                }))
+                .unwrap();
+                service::create_order(state.clone(), customer(user_id), Valid(order))
+            };
+
+            let mut hamsters = Vec::new();
+            for _ in 0..4 {
+                hamsters.push(state.pets.create(hamster()).await?);
+            }
+            let res = place("spring").await.into_response();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let body: Value =
+                serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+            assert_eq!(json!(4000), body["subtotal"]);
+            assert_eq!(json!(2), body["items"].as_array().unwrap().len());
+            assert_eq!(json!(400), body["discounts"][0]["amount"]);
+            assert_eq!(json!("SPRING"), body["discounts"][1]["code"]);
+            assert_eq!(json!(900), body["discount"]);
+            assert_eq!(json!(3100), body["total"]);
+            assert_eq!(None, body.get("coupon"));
+
+            // used up, and the order is not placed without it
+            let res = place("SPRING").await.into_response();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            for pet_id in &hamsters[2..] {
+                assert_eq!(
+                    PetStatus::Available,
+                    state.pets.get(*pet_id as u64).await?.unwrap().status
+                );
+            }
+            let rodents_sale = state.promotions.list().await?[0].clone();
+            assert_eq!(1, rodents_sale.uses, "rolled back with the order");
+
+            let res = place("WINTER").await.into_response();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+            // cancelling gives the uses back
+            let order_id = state.orders.list(user_id as u64).await?[0].id as u64;
+            service::cancel(state.clone(), customer(user_id), Path(order_id))
+                .await
+                .into_response();
+            let rodents_sale = state.promotions.list().await?[0].clone();
+            assert_eq!(0, rodents_sale.uses);
+            let res = place("SPRING").await.into_response();
+            assert_eq!(StatusCode::CREATED, res.status());
+
+            // nothing for orders short of pets, their prices are not known yet
+            let short = |coupon: Option<&str>| {
+                let order: Order = serde_json::from_value(json!({
+                    "pet": {"category": "Rodents", "pet_size": "Terraium"},
+                    "quantity": 3,
+                    "coupon": coupon,
+                }))
+                .unwrap();
+                service::create_order(state.clone(), customer(user_id), Valid(order))
+            };
+            let res = short(Some("SPRING")).await.into_response();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let body: Value =
+                serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+            assert!(body.to_string().contains("back-ordered"));
+            let res = short(None).await.into_response();
+            let body: Value =
+                serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await?)?;
+            assert_eq!(json!("backordered"), body["status"]);
+            assert_eq!(json!([]), body["discounts"]);
+            let rodents_sale = state.promotions.list().await?[0].clone();
+            assert_eq!(1, rodents_sale.uses);
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn order_by_kind() -> anyhow::Result<()> {
+            let state = fixture();
+            let (user_id, _) = seed(&state.0).await?;
+            let house_cat = || PetDB {
+                id: 0,
+                category: Some(PetCategory::Feline),
+                status: PetStatus::Available,
+                size: Some(PetSize::House),
+                price: None,
+                currency: None,
+                photo_urls: Vec::new(),
+                tags: Vec::new(),
+                thumbnails: Vec::new(),
+            };
+            let first_cat = state.pets.create(house_cat()).await?;
+            let order = Order {
+                id: 0,
                 user_id: 0,
-                quantity: 32,
-                ship_date: Some(NaiveDateTime::new(d, t).and_utc()),
-                status: OrderStatus::Approved,
+                items: Vec::new(),
+                pet_id: None,
+                pet: Some(OrderPet {
+                    category: PetCategory::Feline,
+                    pet_size: PetSize::House,
+                }),
+                quantity: Some(1),
+                total_quantity: 0,
+                coupon: None,
+                discounts: Vec::new(),
+                currency: None,
+                subtotal: 0,
+                discount: 0,
+                tax: 0,
+                total: 0,
+                ship_date: None,
+                status: Some(OrderStatus::Awaiting),
             };
 
-            storage::create(state.0.clone(), test_order).await?;
-            storage::update(
-                state.0.clone(),
-                OrderDB {
-                    id: 1,
-                    pet_id: 0,
-                    user_id: 1,
-                    quantity: 0,
-                    ship_date: Some(NaiveDateTime::new(d, t).and_utc()),
-                    status: OrderStatus::Cancelled,
-                },
+            for _ in 0..2 {
+                let res =
+                    service::create_order(state.clone(), customer(user_id), Valid(order.clone()))
+                        .await
+                        .into_response();
+                assert_eq!(StatusCode::CREATED, res.status());
+            }
+            // newest first
+            let [back_order, allocated] = &state.orders.list(user_id as u64).await?[..] else {
+                panic!("expected two orders");
+            };
+            assert_eq!(Some(first_cat), allocated.items[0].pet_id);
+            assert_eq!(OrderStatus::Awaiting, allocated.status);
+            // the seeded cat has no size, so nothing is left for the second
+            assert_eq!(None, back_order.items[0].pet_id);
+            assert_eq!(OrderStatus::BackOrdered, back_order.status);
+
+            let second_cat = state.pets.create(house_cat()).await?;
+            fill_back_orders(&state.0, 7).await?;
+            let filled = state.orders.get(back_order.id as u64).await?.unwrap();
+            assert_eq!(Some(second_cat), filled.items[0].pet_id);
+            assert_eq!(OrderStatus::Awaiting, filled.status);
+            assert_eq!(
+                PetStatus::Pending,
+                state.pets.get(second_cat as u64).await?.unwrap().status
+            );
+            let history = state.orders.history(filled.id as u64).await?;
+            assert_eq!(1, history.len());
+            assert_eq!(OrderStatus::BackOrdered, history[0].from_status);
+            assert_eq!(7, history[0].actor_id);
+
+            // the allocated pet cannot be swapped for another one
+            let swapped = Order {
+                pet_id: Some(first_cat as u64),
+                pet: None,
+                ..order.clone()
+            };
+            let res = service::update_order(
+                state.clone(),
+                customer(user_id),
+                Path(filled.id as u64),
+                Valid(swapped),
             )
-            .await?;
+            .await
+            .into_response();
+            assert_eq!(StatusCode::CONFLICT, res.status());
 
-            let result = storage::get(state.0.clone(), 1).await?;
+            // nor can the quantity change, the discounts were worked out for it
+            let more = Order {
+                quantity: Some(2),
+                ..order.clone()
+            };
+            let res = service::update_order(
+                state.clone(),
+                customer(user_id),
+                Path(filled.id as u64),
+                Valid(more),
+            )
+            .await
+            .into_response();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+
+            // the ship date can, the status may be left out
+            let shipped = Order {
+                ship_date: Some(Utc::now()),
+                status: None,
+                ..order.clone()
+            };
+            let res = service::update_order(
+                state.clone(),
+                customer(user_id),
+                Path(filled.id as u64),
+                Valid(shipped),
+            )
+            .await
+            .into_response();
+            assert_eq!(StatusCode::OK, res.status());
+
+            // and nothing about an order once it is final
+            service::cancel(state.clone(), customer(user_id), Path(filled.id as u64))
+                .await
+                .into_response();
+            let cancelled = Order {
+                status: Some(OrderStatus::Cancelled),
+                ..order
+            };
+            let res = service::update_order(
+                state.clone(),
+                customer(user_id),
+                Path(filled.id as u64),
+                Valid(cancelled),
+            )
+            .await
+            .into_response();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+            Ok(())
+        }
+    }
+
+    mod storage {
+
+        use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+        use chrono::{Duration, Utc};
+
+        use serde_json::json;
 
+        use crate::{
+            orders::{
+                storage::{OrderDB, OrderDiscountDB, OrderItemDB},
+                tests::seed,
+                Order, OrderItem, OrderPet, OrderStatus, MAX_QUANTITY,
+            },
+            pet::{PetCategory, PetSize},
+            promotion::{storage::PromotionDB, Promotion},
+            validation::Validate,
+            AppState,
+        };
+
+        /// An order of `user_id` for the pets with `pet_ids`.
+        fn order_db(user_id: i64, status: OrderStatus, pet_ids: &[Option<i64>]) -> OrderDB {
+            OrderDB {
+                id: 0,
+                user_id,
+                ship_date: None,
+                status,
+                reserved_at: None,
+                tax_rate: 0,
+                discounts: Vec::new(),
+                items: pet_ids
+                    .iter()
+                    .map(|&pet_id| OrderItemDB {
+                        id: 0,
+                        order_id: 0,
+                        pet_id,
+                        quantity: 1,
+                        pet_category: None,
+                        pet_size: None,
+                        unit_price: None,
+                        currency: None,
+                    })
+                    .collect(),
+            }
+        }
+
+        #[test]
+        fn validate_order() {
+            let order = Order {
+                id: 0,
+                user_id: 1,
+                items: Vec::new(),
+                pet_id: Some(1),
+                pet: None,
+                quantity: Some(1),
+                total_quantity: 0,
+                coupon: None,
+                discounts: Vec::new(),
+                currency: None,
+                subtotal: 0,
+                discount: 0,
+                tax: 0,
+                total: 0,
+                ship_date: Some(Utc::now() + Duration::days(1)),
+                status: Some(OrderStatus::Awaiting),
+            };
+            assert!(order.validate().is_empty());
+
+            let errors = Order {
+                quantity: Some(0),
+                ship_date: Some(Utc::now() - Duration::days(1)),
+                ..order.clone()
+            }
+            .validate();
+            let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+            assert_eq!(vec!["quantity", "ship_date"], fields);
+
+            let errors = Order {
+                pet_id: None,
+                ..order.clone()
+            }
+            .validate();
             assert_eq!(
-                Some(OrderDB {
-                    id: 1,
-                    pet_id: 0,
-                    user_id: 1,
-                    quantity: 0,
-                    ship_date: Some(NaiveDateTime::new(d, t).and_utc()),
-                    status: OrderStatus::Cancelled,
+                vec!["pet_id"],
+                errors.iter().map(|e| e.field).collect::<Vec<_>>()
+            );
+
+            // one pet cannot be ordered twice, kinds only so many times
+            let errors = Order {
+                quantity: Some(2),
+                ..order.clone()
+            }
+            .validate();
+            assert_eq!("must be 1 for a particular pet", errors[0].message);
+            let errors = Order {
+                pet_id: None,
+                pet: Some(OrderPet {
+                    category: PetCategory::Birds,
+                    pet_size: PetSize::House,
                 }),
-                result
+                quantity: Some(MAX_QUANTITY + 1),
+                ..order.clone()
+            }
+            .validate();
+            assert_eq!(
+                vec!["quantity"],
+                errors.iter().map(|e| e.field).collect::<Vec<_>>()
+            );
+
+            let item = OrderItem {
+                id: 0,
+                pet_id: Some(1),
+                pet: None,
+                quantity: 1,
+                unit_price: None,
+            };
+            let items = Order {
+                items: vec![
+                    item.clone(),
+                    OrderItem {
+                        quantity: 0,
+                        ..item
+                    },
+                ],
+                pet_id: None,
+                quantity: None,
+                ..order.clone()
+            };
+            let errors = items.validate();
+            assert_eq!(1, errors.len());
+            assert_eq!("item 2: quantity must be at least 1", errors[0].message);
+
+            // either shape, not both
+            let errors = Order {
+                items: items.items[..1].to_vec(),
+                ..order
+            }
+            .validate();
+            assert_eq!(
+                vec!["items"],
+                errors.iter().map(|e| e.field).collect::<Vec<_>>()
             );
+        }
+
+        #[tokio::test]
+        async fn list_back_orders() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, pet_id) = seed(&state).await?;
+                state
+                    .orders
+                    .create(order_db(user_id, OrderStatus::Awaiting, &[Some(pet_id)]))
+                    .await?;
+                let mut back_order = order_db(user_id, OrderStatus::BackOrdered, &[None]);
+                back_order.items[0].pet_category = Some(PetCategory::Feline);
+                back_order.items[0].pet_size = Some(PetSize::House);
+                let created = state.orders.create(back_order).await?;
+
+                assert_eq!(vec![created.clone()], state.orders.back_orders().await?);
+                assert_eq!(
+                    Some(created.clone()),
+                    state.orders.get(created.id as u64).await?
+                );
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn list_expired() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, pet_id) = seed(&state).await?;
+                let now = Utc::now();
+                let order = OrderDB {
+                    reserved_at: Some(now - Duration::hours(2)),
+                    ..order_db(user_id, OrderStatus::Awaiting, &[Some(pet_id)])
+                };
+                let stale = state.orders.create(order.clone()).await?;
+                state
+                    .orders
+                    .create(OrderDB {
+                        reserved_at: Some(now),
+                        ..order.clone()
+                    })
+                    .await?;
+                state
+                    .orders
+                    .create(OrderDB {
+                        status: OrderStatus::Approved,
+                        ..order
+                    })
+                    .await?;
+                let held = state
+                    .orders
+                    .create(OrderDB {
+                        reserved_at: order.reserved_at,
+                        ..order_db(user_id, OrderStatus::BackOrdered, &[Some(pet_id), None])
+                    })
+                    .await?;
+
+                assert_eq!(
+                    vec![stale, held],
+                    state.orders.expired(now - Duration::hours(1)).await?
+                );
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn reject_unknown_pet() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, _) = seed(&state).await?;
+
+                let res = state
+                    .orders
+                    .create(order_db(user_id, OrderStatus::Awaiting, &[Some(-1)]))
+                    .await;
+
+                assert!(crate::error::is_foreign_key_violation(&res.unwrap_err()));
+
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
 
-            storage::delete(state.0.clone(), 1).await?;
-            state.0.shutdown().await?;
+        #[test]
+        fn transition_table() {
+            use OrderStatus::*;
 
+            assert!(Awaiting.can_transition_to(&Approved));
+            assert!(Awaiting.can_transition_to(&Cancelled));
+            assert!(Approved.can_transition_to(&Delivered));
+            assert!(!Awaiting.can_transition_to(&Delivered));
+            assert!(!Delivered.can_transition_to(&Awaiting));
+            assert!(!Cancelled.can_transition_to(&Approved));
+        }
+
+        #[tokio::test]
+        async fn transition_order() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, pet_id) = seed(&state).await?;
+                let order = order_db(user_id, OrderStatus::Awaiting, &[Some(pet_id)]);
+                let id = state.orders.create(order).await?.id as u64;
+
+                let approved = state
+                    .orders
+                    .transition(id, OrderStatus::Awaiting, OrderStatus::Approved, 42)
+                    .await?;
+                // stale `from`, the order is already approved
+                let stale = state
+                    .orders
+                    .transition(id, OrderStatus::Awaiting, OrderStatus::Cancelled, 42)
+                    .await?;
+                let history = state.orders.history(id).await?;
+
+                assert_eq!(Some(OrderStatus::Approved), approved.map(|o| o.status));
+                assert_eq!(None, stale);
+                assert_eq!(1, history.len());
+                assert_eq!(OrderStatus::Awaiting, history[0].from_status);
+                assert_eq!(OrderStatus::Approved, history[0].to_status);
+                assert_eq!(42, history[0].actor_id);
+
+                // the pet is still referenced
+                assert!(state.pets.delete(pet_id as u64).await.is_err());
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn get_order() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let res = state.orders.get(0).await?;
+
+                assert_eq!(None, res);
+
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn insert_order() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, pet_id) = seed(&state).await?;
+                let (_, other_pet) = seed(&state).await?;
+
+                let d = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
+                let t = NaiveTime::from_hms_milli_opt(23, 22, 0, 0).unwrap();
+                let mut test_order = OrderDB {
+                    ship_date: Some(NaiveDateTime::new(d, t).and_utc()),
+                    tax_rate: 1900,
+                    ..order_db(
+                        user_id,
+                        OrderStatus::Approved,
+                        &[Some(pet_id), Some(other_pet)],
+                    )
+                };
+                test_order.items[0].unit_price = Some(1250);
+                test_order.items[0].currency = Some("EUR".to_string());
+                let promotion: Promotion = serde_json::from_value(json!({
+                    "rule": {"type": "category", "category": "Feline"},
+                    "discount": {"type": "free_shipping"},
+                }))?;
+                let promotion_id = state
+                    .promotions
+                    .create(PromotionDB::from(promotion))
+                    .await?;
+                test_order.discounts.push(OrderDiscountDB {
+                    id: 0,
+                    order_id: 0,
+                    promotion_id: Some(promotion_id),
+                    code: None,
+                    amount: 0,
+                    free_shipping: true,
+                });
+                let created = state.orders.create(test_order.clone()).await?;
+                let get_res = state.orders.get(created.id as u64).await?;
+
+                assert_eq!(Some(created.clone()), get_res);
+                assert_eq!(
+                    vec![Some(pet_id), Some(other_pet)],
+                    created
+                        .items
+                        .iter()
+                        .map(|item| item.pet_id)
+                        .collect::<Vec<_>>()
+                );
+                assert!(created.items.iter().all(|item| item.order_id == created.id));
+                assert_eq!(1900, created.tax_rate);
+                assert!(created.discounts[0].free_shipping);
+                assert_eq!(test_order.items[0].currency, created.items[0].currency);
+                assert_eq!(
+                    vec![created.clone()],
+                    state.orders.list(user_id as u64).await?
+                );
+                // orders keep their discounts when the promotion goes
+                state.promotions.delete(promotion_id as u64).await?;
+                let kept = state.orders.get(created.id as u64).await?.unwrap();
+                assert_eq!(None, kept.discounts[0].promotion_id);
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn update_order() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let (user_id, pet_id) = seed(&state).await?;
+                let d = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
+                let t = NaiveTime::from_hms_milli_opt(23, 22, 0, 0).unwrap();
+
+                let test_order = order_db(user_id, OrderStatus::BackOrdered, &[None]);
+                let created = state.orders.create(test_order).await?;
+                let mut changed = OrderDB {
+                    ship_date: Some(NaiveDateTime::new(d, t).and_utc()),
+                    status: OrderStatus::Cancelled,
+                    ..created.clone()
+                };
+                changed.items[0].pet_id = Some(pet_id);
+                changed.items[0].quantity = 3;
+                let updated = state.orders.update(changed.clone()).await?;
+                let missing = state
+                    .orders
+                    .update(OrderDB {
+                        id: -1,
+                        ..changed.clone()
+                    })
+                    .await?;
+
+                let result = state.orders.get(created.id as u64).await?;
+
+                assert_eq!(updated, result);
+                assert_eq!(None, missing);
+                assert_eq!(Some(changed), result);
+                state.shutdown().await?;
+            }
             Ok(())
         }
     }
@@ -0,0 +1,84 @@
+use axum::http::Uri;
+use serde::Serialize;
+
+pub const DEFAULT_PER_PAGE: u64 = 20;
+pub const MAX_PER_PAGE: u64 = 100;
+/// Keeps the offset of the last page far from overflowing.
+pub const MAX_PAGE: u64 = 1_000_000;
+
+pub fn first_page() -> u64 {
+    1
+}
+
+pub fn default_per_page() -> u64 {
+    DEFAULT_PER_PAGE
+}
+
+/// One page of a listing. `next` and `prev` repeat the request with only
+/// `page` changed, and are left out on the last and the first page.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// `uri` is the request's original uri, so the links include the prefix
+    /// the router was nested under.
+    pub fn new(items: Vec<T>, total: u64, page: u64, per_page: u64, uri: &Uri) -> Page<T> {
+        let next = (page.saturating_mul(per_page) < total).then(|| link(uri, page + 1));
+        let prev = (page > 1).then(|| link(uri, page - 1));
+        Page {
+            items,
+            total,
+            page,
+            per_page,
+            next,
+            prev,
+        }
+    }
+}
+
+fn link(uri: &Uri, page: u64) -> String {
+    let mut params: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("page="))
+        .collect();
+    let page = format!("page={page}");
+    params.push(&page);
+    format!("{}?{}", uri.path(), params.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use super::Page;
+
+    #[test]
+    fn links_keep_the_query() {
+        let uri = Uri::from_static("/pets?status=sold&page=2&per_page=10");
+        let page = Page::new(vec![(); 10], 35, 2, 10, &uri);
+
+        assert_eq!(
+            Some("/pets?status=sold&per_page=10&page=3"),
+            page.next.as_deref()
+        );
+        assert_eq!(
+            Some("/pets?status=sold&per_page=10&page=1"),
+            page.prev.as_deref()
+        );
+
+        let last = Page::new(vec![(); 5], 35, 4, 10, &Uri::from_static("/pets"));
+        assert_eq!(None, last.next);
+        assert_eq!(Some("/pets?page=3"), last.prev.as_deref());
+    }
+}
@@ -1,65 +1,582 @@
-use anyhow::Result;
-use std::time::Duration;
+use anyhow::{bail, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use axum::async_trait;
+use sqlx::{
+    error::{DatabaseError, ErrorKind},
+    pool::PoolConnection,
+    postgres::PgPoolOptions,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Database, PgPool, Postgres, Sqlite, SqlitePool, Transaction,
+};
+use tokio::sync::OwnedMutexGuard;
 
+use crate::{
+    cart::storage::{CartDB, CartItemDB, CartRepository},
+    config::AppConfig,
+    orders::storage::{OrderDB, OrderDiscountDB, OrderItemDB, OrderRepository, OrderTransitionDB},
+    pet::storage::{PetDB, PetRepository, PhotoDB, ThumbnailDB},
+    promotion::storage::{PromotionDB, PromotionRepository},
+    tag::storage::{TagDB, TagRepository},
+    user::storage::{UserDB, UserRepository},
+};
+
+/// `db.url` selecting [`MemoryDb`] instead of a database server.
+pub const MEMORY_URL: &str = "memory:";
+
+/// Whether `url` points at a Postgres server, which libpq spells either
+/// `postgres://` or `postgresql://`.
+pub fn is_postgres(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+#[allow(async_fn_in_trait)]
 pub trait Storage {
     type DB;
     async fn conn(self, config: StorageConfig) -> Result<Self::DB>;
-    async fn migrate(self, pool: ArcPgPool) -> Result<()>;
-    async fn close(self, pool: ArcPgPool);
+    async fn migrate(self, pool: Self::DB) -> Result<()>;
+    async fn close(self, pool: Self::DB);
 }
 
 pub struct StorageConfig {
     pub db_path: String,
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+}
+
+impl From<&AppConfig> for StorageConfig {
+    fn from(config: &AppConfig) -> Self {
+        StorageConfig {
+            db_path: config.db(),
+            max_connections: config.db_max_connections(),
+            acquire_timeout: config.db_acquire_timeout(),
+        }
+    }
+}
+
+/// A connection pool for whichever backend the configured url points at.
+#[derive(Debug, Clone)]
+pub enum DbPool {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+type SharedTx<DB> = Arc<tokio::sync::Mutex<Option<Transaction<'static, DB>>>>;
+
+/// Where the SQL repositories run their queries: on the pool, or inside the
+/// transaction of a [`UnitOfWork`].
+///
+/// Queries are written once with `$N` placeholders, which both backends
+/// understand, and run through [`on_conn`].
+#[derive(Clone)]
+pub enum Sql {
+    Pool(DbPool),
+    PostgresTx(SharedTx<Postgres>),
+    SqliteTx(SharedTx<Sqlite>),
+}
+
+impl std::fmt::Debug for Sql {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sql::Pool(pool) => f.debug_tuple("Pool").field(pool).finish(),
+            Sql::PostgresTx(_) | Sql::SqliteTx(_) => f.write_str("Tx"),
+        }
+    }
+}
+
+/// A connection checked out of the pool or borrowed from a transaction.
+pub(crate) enum Conn<DB: Database> {
+    Pool(PoolConnection<DB>),
+    Tx(OwnedMutexGuard<Option<Transaction<'static, DB>>>),
 }
 
-pub type ArcPgPool = Pool<Postgres>;
-pub struct StorageIml;
-impl Storage for StorageIml {
-    type DB = ArcPgPool;
+impl<DB: Database> Deref for Conn<DB> {
+    type Target = DB::Connection;
 
-    async fn conn(self, config: StorageConfig) -> Result<ArcPgPool> {
+    fn deref(&self) -> &DB::Connection {
+        match self {
+            Conn::Pool(conn) => conn,
+            Conn::Tx(tx) => tx.as_ref().expect("checked by Sql::conn"),
+        }
+    }
+}
+
+impl<DB: Database> DerefMut for Conn<DB> {
+    fn deref_mut(&mut self) -> &mut DB::Connection {
+        match self {
+            Conn::Pool(conn) => conn,
+            Conn::Tx(tx) => tx.as_mut().expect("checked by Sql::conn"),
+        }
+    }
+}
+
+// only ever lives for the duration of one repository call
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum AnyConn {
+    Postgres(Conn<Postgres>),
+    Sqlite(Conn<Sqlite>),
+}
+
+impl Sql {
+    pub(crate) async fn conn(&self) -> Result<AnyConn> {
+        async fn lock<DB: Database>(tx: &SharedTx<DB>) -> Result<Conn<DB>> {
+            let tx = tx.clone().lock_owned().await;
+            if tx.is_none() {
+                bail!("the unit of work has already been committed");
+            }
+            Ok(Conn::Tx(tx))
+        }
+
+        Ok(match self {
+            Sql::Pool(DbPool::Postgres(pool)) => {
+                AnyConn::Postgres(Conn::Pool(pool.acquire().await?))
+            }
+            Sql::Pool(DbPool::Sqlite(pool)) => AnyConn::Sqlite(Conn::Pool(pool.acquire().await?)),
+            Sql::PostgresTx(tx) => AnyConn::Postgres(lock(tx).await?),
+            Sql::SqliteTx(tx) => AnyConn::Sqlite(lock(tx).await?),
+        })
+    }
+}
+
+/// Evaluates `$body` with `$conn` bound to a `&mut` connection of `$db`, a
+/// [`Sql`]. The body is compiled once per backend, so sqlx infers the database
+/// from the executor and no `query_as::<Postgres, _>` turbofish is needed.
+macro_rules! on_conn {
+    ($db:expr, |$conn:ident| $body:expr) => {
+        match $db.conn().await? {
+            $crate::persistence::AnyConn::Postgres(mut conn) => {
+                let $conn = &mut *conn;
+                $body
+            }
+            $crate::persistence::AnyConn::Sqlite(mut conn) => {
+                let $conn = &mut *conn;
+                $body
+            }
+        }
+    };
+}
+pub(crate) use on_conn;
+
+/// `$1, $2, …` binding `count` values, for an `in (…)` clause.
+pub(crate) fn in_params(count: usize) -> String {
+    let params: Vec<String> = (1..=count).map(|i| format!("${i}")).collect();
+    params.join(", ")
+}
+
+/// Repositories sharing one transaction. Their changes become visible to
+/// everybody else on [`UnitOfWork::commit`], dropping the unit of work instead
+/// rolls all of them back, so bailing out with `?` undoes a failed service call.
+pub struct UnitOfWork {
+    pub carts: Arc<dyn CartRepository>,
+    pub orders: Arc<dyn OrderRepository>,
+    pub pets: Arc<dyn PetRepository>,
+    pub promotions: Arc<dyn PromotionRepository>,
+    pub tags: Arc<dyn TagRepository>,
+    pub users: Arc<dyn UserRepository>,
+    tx: Box<dyn Commit>,
+}
+
+#[async_trait]
+trait Commit: Send + Sync {
+    async fn commit(self: Box<Self>) -> Result<()>;
+}
+
+impl UnitOfWork {
+    fn new<R>(repositories: R, tx: impl Commit + 'static) -> UnitOfWork
+    where
+        R: CartRepository
+            + OrderRepository
+            + PetRepository
+            + PromotionRepository
+            + TagRepository
+            + UserRepository
+            + Clone
+            + 'static,
+    {
+        UnitOfWork {
+            carts: Arc::new(repositories.clone()),
+            orders: Arc::new(repositories.clone()),
+            pets: Arc::new(repositories.clone()),
+            promotions: Arc::new(repositories.clone()),
+            tags: Arc::new(repositories.clone()),
+            users: Arc::new(repositories),
+            tx: Box::new(tx),
+        }
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        self.tx.commit().await
+    }
+}
+
+/// A store that can hand out a [`UnitOfWork`].
+#[async_trait]
+pub trait Transactional: Debug + Send + Sync {
+    async fn begin(&self) -> Result<UnitOfWork>;
+    async fn close(&self);
+}
+
+#[async_trait]
+impl Commit for Sql {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        match *self {
+            Sql::PostgresTx(tx) => {
+                if let Some(tx) = tx.lock().await.take() {
+                    tx.commit().await?;
+                }
+            }
+            Sql::SqliteTx(tx) => {
+                if let Some(tx) = tx.lock().await.take() {
+                    tx.commit().await?;
+                }
+            }
+            Sql::Pool(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transactional for Sql {
+    async fn begin(&self) -> Result<UnitOfWork> {
+        let tx = match self {
+            Sql::Pool(DbPool::Postgres(pool)) => {
+                Sql::PostgresTx(Arc::new(tokio::sync::Mutex::new(Some(pool.begin().await?))))
+            }
+            Sql::Pool(DbPool::Sqlite(pool)) => {
+                Sql::SqliteTx(Arc::new(tokio::sync::Mutex::new(Some(pool.begin().await?))))
+            }
+            Sql::PostgresTx(_) | Sql::SqliteTx(_) => bail!("units of work cannot be nested"),
+        };
+        Ok(UnitOfWork::new(tx.clone(), tx))
+    }
+
+    async fn close(&self) {
+        if let Sql::Pool(pool) = self {
+            pool.close().await
+        }
+    }
+}
+
+impl DbPool {
+    /// Connects and migrates, picking the backend from the url scheme.
+    pub async fn connect(config: StorageConfig) -> Result<DbPool> {
+        let pool = if is_postgres(&config.db_path) {
+            let pool = PgStorage.conn(config).await?;
+            PgStorage.migrate(pool.clone()).await?;
+            DbPool::Postgres(pool)
+        } else if config.db_path.starts_with("sqlite:") {
+            let pool = SqliteStorage.conn(config).await?;
+            SqliteStorage.migrate(pool.clone()).await?;
+            DbPool::Sqlite(pool)
+        } else {
+            bail!("unsupported database url, expected postgres://, postgresql:// or sqlite:");
+        };
+
+        Ok(pool)
+    }
+
+    pub async fn close(&self) {
+        match self {
+            DbPool::Postgres(pool) => PgStorage.close(pool.clone()).await,
+            DbPool::Sqlite(pool) => SqliteStorage.close(pool.clone()).await,
+        }
+    }
+}
+
+pub struct PgStorage;
+impl Storage for PgStorage {
+    type DB = PgPool;
+
+    async fn conn(self, config: StorageConfig) -> Result<PgPool> {
         let pool = PgPoolOptions::new()
-            .max_connections(5)
+            .max_connections(config.max_connections)
             .idle_timeout(None)
             .max_lifetime(None)
-            .acquire_timeout(Duration::from_millis(300))
+            .acquire_timeout(config.acquire_timeout)
             .connect(&config.db_path)
             .await?;
 
         Ok(pool)
     }
 
-    async fn close(self, pool: ArcPgPool) {
+    async fn close(self, pool: PgPool) {
+        pool.close().await
+    }
+
+    async fn migrate(self, pool: PgPool) -> Result<()> {
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+        Ok(())
+    }
+}
+
+pub struct SqliteStorage;
+impl Storage for SqliteStorage {
+    type DB = SqlitePool;
+
+    async fn conn(self, config: StorageConfig) -> Result<SqlitePool> {
+        let options = SqliteConnectOptions::from_str(&config.db_path)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        // every connection to `sqlite::memory:` opens its own empty database
+        let max_connections = if config.db_path.contains(":memory:") {
+            1
+        } else {
+            config.max_connections
+        };
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .acquire_timeout(config.acquire_timeout)
+            .connect_with(options)
+            .await?;
+
+        Ok(pool)
+    }
+
+    async fn close(self, pool: SqlitePool) {
         pool.close().await
     }
 
-    async fn migrate(self, pool: ArcPgPool) -> Result<()> {
-        sqlx::migrate!("./migrations").run(&pool.clone()).await?;
+    async fn migrate(self, pool: SqlitePool) -> Result<()> {
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        Ok(())
+    }
+}
+
+/// Keeps every table in process memory, for tests and throwaway instances.
+/// Implements the same repositories as [`DbPool`], including the unique and
+/// foreign key constraints the services rely on.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDb {
+    tables: Arc<Mutex<Tables>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tables {
+    pub users: BTreeMap<i64, UserDB>,
+    pub pets: BTreeMap<i64, PetDB>,
+    pub pet_photos: BTreeMap<i64, PhotoDB>,
+    /// By `(photo_url, size)`.
+    pub photo_thumbnails: BTreeMap<(String, i64), ThumbnailDB>,
+    pub tags: BTreeMap<i64, TagDB>,
+    /// `(pet_id, tag_id)` pairs.
+    pub pet_tags: BTreeSet<(i64, i64)>,
+    /// Without their `items` and `discounts`, which are kept in `order_items`
+    /// and `order_discounts`.
+    pub orders: BTreeMap<i64, OrderDB>,
+    pub order_items: BTreeMap<i64, OrderItemDB>,
+    pub order_discounts: BTreeMap<i64, OrderDiscountDB>,
+    pub promotions: BTreeMap<i64, PromotionDB>,
+    pub order_status_history: BTreeMap<i64, OrderTransitionDB>,
+    /// By `user_id`, without their `items`, which are kept in `cart_items`.
+    pub carts: BTreeMap<i64, CartDB>,
+    pub cart_items: BTreeMap<i64, CartItemDB>,
+    last_id: i64,
+}
+
+impl Tables {
+    /// Ids come from one sequence shared by all tables, like `bigserial` they
+    /// are never reused.
+    pub fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+}
+
+impl MemoryDb {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Tables> {
+        // a panicking test must not poison the tables for the others
+        self.tables.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Works on a copy of the tables which replaces the original ones on commit.
+/// Good enough for tests, but concurrent writes outside the unit of work are
+/// lost when it commits, which is why only the dev profile may select it.
+struct MemoryTx {
+    origin: MemoryDb,
+    work: MemoryDb,
+}
+
+#[async_trait]
+impl Commit for MemoryTx {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        let work = self.work.lock().clone();
+        *self.origin.lock() = work;
         Ok(())
     }
 }
 
+#[async_trait]
+impl Transactional for MemoryDb {
+    async fn begin(&self) -> Result<UnitOfWork> {
+        let work = MemoryDb {
+            tables: Arc::new(Mutex::new(self.lock().clone())),
+        };
+        Ok(UnitOfWork::new(
+            work.clone(),
+            MemoryTx {
+                origin: self.clone(),
+                work,
+            },
+        ))
+    }
+
+    async fn close(&self) {}
+}
+
+/// A constraint violation raised by [`MemoryDb`], wrapped in a `sqlx::Error`
+/// so `error::is_unique_violation` and friends recognize it.
+#[derive(thiserror::Error, Debug)]
+#[error("{constraint} violated")]
+struct ConstraintViolation {
+    constraint: &'static str,
+    unique: bool,
+}
+
+impl DatabaseError for ConstraintViolation {
+    fn message(&self) -> &str {
+        self.constraint
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        Some(self.constraint)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        if self.unique {
+            ErrorKind::UniqueViolation
+        } else {
+            ErrorKind::ForeignKeyViolation
+        }
+    }
+}
+
+pub(crate) fn unique_violation(constraint: &'static str) -> anyhow::Error {
+    sqlx::Error::Database(Box::new(ConstraintViolation {
+        constraint,
+        unique: true,
+    }))
+    .into()
+}
+
+pub(crate) fn foreign_key_violation(constraint: &'static str) -> anyhow::Error {
+    sqlx::Error::Database(Box::new(ConstraintViolation {
+        constraint,
+        unique: false,
+    }))
+    .into()
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use crate::{
         config::AppConfig,
-        persistence::{Storage, StorageConfig, StorageIml},
+        persistence::{DbPool, SqliteStorage, Storage, StorageConfig},
+        pet::{storage::PetDB, PetStatus},
+        AppState,
     };
 
     #[tokio::test]
+    #[ignore = "needs the database of the active profile, run with --ignored"]
     async fn migration_test() -> anyhow::Result<()> {
         let config = AppConfig::load_config()?;
 
+        let pool = DbPool::connect(StorageConfig::from(&config)).await;
+
+        assert!(pool.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_migration_test() -> anyhow::Result<()> {
         let storage_conf = StorageConfig {
-            db_path: config.db(),
+            db_path: "sqlite::memory:".to_string(),
+            max_connections: 5,
+            acquire_timeout: Duration::from_millis(300),
         };
-        let pool = StorageIml.conn(storage_conf).await?;
-        let storage = StorageIml.migrate(pool).await;
+        let pool = SqliteStorage.conn(storage_conf).await?;
+        SqliteStorage.migrate(pool.clone()).await?;
 
-        assert!(storage.is_ok());
+        let (tables,): (i64,) = sqlx::query_as(
+            "select count(*) from sqlite_master
+            where type = 'table'
+            and name in (
+                'users', 'pets', 'pet_photos', 'photo_thumbnails', 'tags', 'pet_tags', 'orders',
+                'order_items', 'order_status_history', 'promotions', 'order_discounts',
+                'carts', 'cart_items'
+            )",
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(13, tables);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn unit_of_work() -> anyhow::Result<()> {
+        for state in AppState::test_backends().await? {
+            let pet = PetDB {
+                id: 0,
+                category: None,
+                status: PetStatus::Available,
+                size: None,
+                price: None,
+                currency: None,
+                photo_urls: Vec::new(),
+                tags: Vec::new(),
+                thumbnails: Vec::new(),
+            };
+
+            let uow = state.begin().await?;
+            let rolled_back = uow.pets.create(pet.clone()).await?;
+            assert!(uow.pets.get(rolled_back as u64).await?.is_some());
+            drop(uow);
+            assert_eq!(None, state.pets.get(rolled_back as u64).await?);
+
+            let uow = state.begin().await?;
+            let committed = uow.pets.create(pet).await?;
+            uow.commit().await?;
+            assert!(state.pets.get(committed as u64).await?.is_some());
+
+            state.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_scheme() {
+        let storage_conf = StorageConfig {
+            db_path: "mysql://localhost/pets".to_string(),
+            max_connections: 5,
+            acquire_timeout: Duration::from_millis(300),
+        };
+
+        assert!(DbPool::connect(storage_conf).await.is_err());
+    }
 }
@@ -1,19 +1,37 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct PetTag {
-    id: u32,
-    name: String,
+use crate::{
+    error::FieldError,
+    pagination::{default_per_page, first_page, MAX_PAGE, MAX_PER_PAGE},
+    validation::{Errors, Validate},
+};
+
+use self::storage::{PetFilter, PetSort};
+
+/// A label pets can carry, e.g. `hypoallergenic`. Tag names are unique, a
+/// pet's tags are matched by name and the `id` clients send is ignored.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PetTag {
+    #[serde(default)]
+    pub id: u32,
+    pub name: String,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-enum PetStatus {
+#[derive(Serialize, Deserialize, sqlx::Type, PartialEq, Debug, Clone)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PetStatus {
     Available,
     Pending,
     Sold,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+impl PetStatus {
+    pub const ALL: [PetStatus; 3] = [PetStatus::Available, PetStatus::Pending, PetStatus::Sold];
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, PartialEq, Debug, Clone)]
+#[sqlx(type_name = "varchar")]
 pub enum PetCategory {
     Amphibians,
     Birds,
@@ -24,18 +42,1762 @@ pub enum PetCategory {
     Feline,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, sqlx::Type, PartialEq, Debug, Clone)]
+#[sqlx(type_name = "varchar")]
 pub enum PetSize {
     Flat,
     House,
     Terraium,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 struct Pet {
-    id: u32,
+    #[serde(default)]
+    id: u64,
     category: PetCategory,
-    photo_urls: String,
-    tags: PetTag,
+    /// Links to the pet's photos, uploaded ones are served under `/media`.
+    /// Also accepts the single url pets carried before.
+    #[serde(default, deserialize_with = "one_or_many")]
+    photo_urls: Vec<String>,
+    /// `photo_urls` along with the thumbnails of the uploaded ones, which
+    /// show up shortly after the upload. Ignored on input.
+    #[serde(default, skip_deserializing)]
+    photos: Vec<PetPhoto>,
+    /// Also accepts the single tag object pets carried before.
+    #[serde(default, deserialize_with = "one_or_many")]
+    tags: Vec<PetTag>,
+    /// Reserving, selling and releasing the pet for orders moves it on,
+    /// updates keep it as it is.
     status: PetStatus,
+    #[serde(default)]
+    size: Option<PetSize>,
+    /// What the pet costs, pets without a price are given away.
+    #[serde(default)]
+    price: Option<Price>,
+}
+
+/// An amount of money, e.g. `{"amount": 1250, "currency": "EUR"}` for 12.50 €.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Price {
+    /// In the minor unit of the currency, e.g. cents.
+    pub amount: u64,
+    /// The ISO 4217 code, e.g. `EUR`.
+    pub currency: String,
+}
+
+/// Whether `code` looks like an ISO 4217 currency code, three capital letters.
+pub(crate) fn is_currency_code(code: &str) -> bool {
+    code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase())
+}
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+struct PetPhoto {
+    url: String,
+    thumbnails: Vec<PetThumbnail>,
+}
+
+/// A copy of a photo fitted into a `size` pixels square.
+#[derive(Serialize, PartialEq, Debug, Clone)]
+struct PetThumbnail {
+    size: u32,
+    url: String,
+}
+
+/// Reads a list, or a lone value as a list of one.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+impl Validate for Pet {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(
+                self.photo_urls.iter().all(|url| !url.trim().is_empty()),
+                "photo_urls",
+                "must not be empty",
+            )
+            .check(
+                self.tags.iter().all(|tag| !tag.name.trim().is_empty()),
+                "tags.name",
+                "must not be empty",
+            )
+            .check(
+                self.price
+                    .as_ref()
+                    .is_none_or(|price| is_currency_code(&price.currency)),
+                "price.currency",
+                "must be an ISO 4217 code such as EUR",
+            )
+            .check(
+                self.price
+                    .as_ref()
+                    .is_none_or(|price| i64::try_from(price.amount).is_ok()),
+                "price.amount",
+                "is too large",
+            )
+            .finish()
+    }
+}
+
+/// Query of `GET /pets`, e.g. `?status=available&tag=hypoallergenic&sort=-id&page=2`.
+#[derive(Deserialize, Debug)]
+struct PetSearch {
+    status: Option<PetStatus>,
+    category: Option<PetCategory>,
+    size: Option<PetSize>,
+    /// Name of a tag the pets carry.
+    tag: Option<String>,
+    sort: Option<String>,
+    #[serde(default = "first_page")]
+    page: u64,
+    #[serde(default = "default_per_page")]
+    per_page: u64,
+}
+
+impl PetSearch {
+    fn filter(&self) -> PetFilter {
+        PetFilter {
+            status: self.status.clone(),
+            category: self.category.clone(),
+            size: self.size.clone(),
+            tag: self.tag.clone(),
+            currency: None,
+        }
+    }
+
+    /// Oldest pets first unless asked otherwise.
+    fn sort(&self) -> Option<PetSort> {
+        PetSort::parse(self.sort.as_deref().unwrap_or("id"))
+    }
+}
+
+impl Validate for PetSearch {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(
+                self.sort().is_some(),
+                "sort",
+                "must be one of id, category or status, prefixed with - for descending order",
+            )
+            .check(
+                (1..=MAX_PAGE).contains(&self.page),
+                "page",
+                "must be between 1 and 1000000",
+            )
+            .check(
+                (1..=MAX_PER_PAGE).contains(&self.per_page),
+                "per_page",
+                "must be between 1 and 100",
+            )
+            .finish()
+    }
+}
+
+mod service {
+    use axum::{
+        body::Bytes,
+        extract::{
+            multipart::{MultipartError, MultipartRejection},
+            Multipart, OriginalUri, Path, State,
+        },
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    };
+
+    use crate::{
+        auth::AuthUser,
+        error::{is_foreign_key_violation, AppError, FieldError},
+        media::{self, ImageType},
+        orders,
+        pagination::Page,
+        validation::{Valid, ValidQuery},
+        AppState,
+    };
+
+    use super::{
+        storage::{PetDB, ThumbnailDB},
+        Pet, PetSearch,
+    };
+
+    pub async fn find_pets(
+        state: State<AppState>,
+        OriginalUri(uri): OriginalUri,
+        ValidQuery(search): ValidQuery<PetSearch>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let sort = search.sort().expect("sort is checked by validate");
+        let offset = (search.page - 1) * search.per_page;
+        let (pets, total) = state
+            .pets
+            .search(&search.filter(), sort, search.per_page, offset)
+            .await?;
+        let pets = pets
+            .into_iter()
+            .map(Pet::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((
+            StatusCode::OK,
+            Json(Page::new(pets, total, search.page, search.per_page, &uri)),
+        ))
+    }
+
+    pub async fn get_pet(
+        state: State<AppState>,
+        Path(pet_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let pet = state
+            .pets
+            .get(pet_id)
+            .await?
+            .ok_or(AppError::NotFound("pet"))?;
+        Ok((StatusCode::OK, Json(Pet::try_from(pet)?)))
+    }
+
+    pub async fn create_pet(
+        state: State<AppState>,
+        auth: AuthUser,
+        Valid(pet): Valid<Pet>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let id = state.pets.create(PetDB::from(pet)).await?;
+        fill_back_orders(&state, &auth).await;
+        let pet = state
+            .pets
+            .get(id as u64)
+            .await?
+            .ok_or(AppError::NotFound("pet"))?;
+        Ok((StatusCode::CREATED, Json(Pet::try_from(pet)?)))
+    }
+
+    pub async fn update_pet(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(pet_id): Path<u64>,
+        Valid(pet): Valid<Pet>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let pet = Pet { id: pet_id, ..pet };
+        let before = state.pets.get(pet_id).await?;
+        if !state.pets.update(PetDB::from(pet)).await? {
+            return Err(AppError::NotFound("pet"));
+        }
+        fill_back_orders(&state, &auth).await;
+        let pet = state
+            .pets
+            .get(pet_id)
+            .await?
+            .ok_or(AppError::NotFound("pet"))?;
+        if let Some(before) = before {
+            discard_uploads(&state, pet_id, before.photo_urls, &pet.photo_urls).await;
+        }
+        Ok((StatusCode::OK, Json(Pet::try_from(pet)?)))
+    }
+
+    pub async fn delete(
+        state: State<AppState>,
+        Path(pet_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let pet = state.pets.get(pet_id).await?;
+        state.pets.delete(pet_id).await.map_err(|err| {
+            if is_foreign_key_violation(&err) {
+                AppError::Conflict {
+                    code: "pet_has_orders",
+                    detail: "the pet is referenced by orders".to_string(),
+                }
+            } else {
+                AppError::Internal(err)
+            }
+        })?;
+        if let Some(pet) = pet {
+            discard_uploads(&state, pet_id, pet.photo_urls, &[]).await;
+        }
+        Ok((StatusCode::OK, Json(())))
+    }
+
+    /// Hands pets that are available now to the orders waiting for their
+    /// kind. The pet is saved already, so a failure is only logged.
+    async fn fill_back_orders(state: &AppState, auth: &AuthUser) {
+        if let Err(err) = orders::fill_back_orders(state, auth.id).await {
+            tracing::warn!("could not fill back-orders: {err:?}");
+        }
+    }
+
+    /// Stores the image sent as the `photo` field of a multipart body and
+    /// appends its url to the pet's `photo_urls`.
+    pub async fn upload_photo(
+        state: State<AppState>,
+        Path(pet_id): Path<u64>,
+        multipart: Result<Multipart, MultipartRejection>,
+    ) -> Result<impl IntoResponse, AppError> {
+        if state.pets.get(pet_id).await?.is_none() {
+            return Err(AppError::NotFound("pet"));
+        }
+        let mut multipart = multipart.map_err(|rejection| invalid_upload(rejection.body_text()))?;
+        let photo = read_photo(&mut multipart, state.media.max_upload_bytes).await?;
+        let image = ImageType::sniff(&photo).ok_or(AppError::UnsupportedMediaType(
+            "photos must be PNG, JPEG, GIF or WebP images",
+        ))?;
+
+        let key = format!(
+            "{}{}.{}",
+            upload_prefix(pet_id),
+            uuid::Uuid::new_v4(),
+            image.extension()
+        );
+        state.media.blobs.put(&key, photo.clone()).await?;
+        if !state.pets.add_photo(pet_id, media::url(&key)).await? {
+            state.media.blobs.delete(&key).await?;
+            return Err(AppError::NotFound("pet"));
+        }
+        tokio::spawn(make_thumbnails(state.0.clone(), key, photo));
+        let pet = state
+            .pets
+            .get(pet_id)
+            .await?
+            .ok_or(AppError::NotFound("pet"))?;
+        Ok((StatusCode::CREATED, Json(Pet::try_from(pet)?)))
+    }
+
+    fn invalid_upload(message: String) -> AppError {
+        AppError::Validation(vec![FieldError {
+            field: "photo",
+            message,
+        }])
+    }
+
+    /// A body cut off by the limit of the route is too large, anything else
+    /// is not a valid upload.
+    fn upload_error(err: MultipartError, limit: usize) -> AppError {
+        if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return AppError::PayloadTooLarge { limit };
+        }
+        invalid_upload(err.body_text())
+    }
+
+    /// The contents of the `photo` field, skipping any other fields. Stops
+    /// reading once it grows beyond `limit` bytes.
+    async fn read_photo(multipart: &mut Multipart, limit: usize) -> Result<Bytes, AppError> {
+        while let Some(mut field) = multipart
+            .next_field()
+            .await
+            .map_err(|err| upload_error(err, limit))?
+        {
+            if field.name() != Some("photo") {
+                continue;
+            }
+            let mut photo = Vec::new();
+            while let Some(chunk) = field
+                .chunk()
+                .await
+                .map_err(|err| upload_error(err, limit))?
+            {
+                if photo.len() + chunk.len() > limit {
+                    return Err(AppError::PayloadTooLarge { limit });
+                }
+                photo.extend_from_slice(&chunk);
+            }
+            return Ok(Bytes::from(photo));
+        }
+        Err(invalid_upload("is required".to_string()))
+    }
+
+    /// Stores and records the thumbnails of the photo uploaded under `key`.
+    /// Runs after the upload has been answered, so failures are only logged.
+    async fn make_thumbnails(state: AppState, key: String, photo: Bytes) {
+        if let Err(err) = store_thumbnails(&state, &key, photo).await {
+            tracing::warn!("could not make thumbnails of {key}: {err:?}");
+        }
+    }
+
+    async fn store_thumbnails(state: &AppState, key: &str, photo: Bytes) -> anyhow::Result<()> {
+        let thumbnails = tokio::task::spawn_blocking(move || media::thumbnails(&photo)).await??;
+        for thumbnail in thumbnails {
+            let thumbnail_key = thumbnail.key(key);
+            let bytes = Bytes::from(thumbnail.bytes);
+            state.media.blobs.put(&thumbnail_key, bytes).await?;
+            let recorded = state
+                .pets
+                .add_thumbnail(ThumbnailDB {
+                    photo_url: media::url(key),
+                    size: thumbnail.size as i64,
+                    url: media::url(&thumbnail_key),
+                })
+                .await?;
+            if !recorded {
+                // the photo is gone already, and with it the thumbnails made so far
+                state.media.blobs.delete(&thumbnail_key).await?;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the photos uploaded for a pet are kept in the blob store.
+    fn upload_prefix(pet_id: u64) -> String {
+        format!("pets/{pet_id}/")
+    }
+
+    /// Deletes the files uploaded for the pet whose urls are among `dropped`
+    /// but not in `kept`, along with their thumbnails. Urls pointing elsewhere
+    /// are left alone, and a failure only leaves an orphaned file behind.
+    async fn discard_uploads(state: &AppState, pet_id: u64, dropped: Vec<String>, kept: &[String]) {
+        let prefix = upload_prefix(pet_id);
+        for url in dropped.iter().filter(|url| !kept.contains(url)) {
+            let Some(key) = media::key(url).filter(|key| key.starts_with(&prefix)) else {
+                continue;
+            };
+            if let Err(err) = discard_upload(state, key, url).await {
+                tracing::warn!("could not delete {key}: {err:?}");
+            }
+        }
+    }
+
+    async fn discard_upload(state: &AppState, key: &str, url: &str) -> anyhow::Result<()> {
+        for thumbnail in state.pets.delete_thumbnails(url).await? {
+            if let Some(thumbnail_key) = media::key(&thumbnail.url) {
+                state.media.blobs.delete(thumbnail_key).await?;
+            }
+        }
+        state.media.blobs.delete(key).await
+    }
+}
+
+pub(crate) mod storage {
+    use std::{cmp::Ordering, fmt::Debug};
+
+    use axum::async_trait;
+    use serde::Serialize;
+    use sqlx::FromRow;
+
+    use sqlx::Connection;
+
+    use crate::{
+        persistence::{foreign_key_violation, in_params, on_conn, MemoryDb, Sql, Tables},
+        tag::storage::TagDB,
+    };
+
+    use super::{Pet, PetCategory, PetPhoto, PetSize, PetStatus, PetTag, PetThumbnail, Price};
+    use anyhow::Result;
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct PetDB {
+        pub id: i64,
+        pub category: Option<PetCategory>,
+        pub status: PetStatus,
+        pub size: Option<PetSize>,
+        /// In the minor unit of `currency`, both are `None` for pets without
+        /// a price.
+        pub price: Option<i64>,
+        pub currency: Option<String>,
+        /// Kept in `pet_photos`, in the order they were added.
+        #[sqlx(skip)]
+        pub photo_urls: Vec<String>,
+        /// Kept in `pet_tags`, ordered by name.
+        #[sqlx(skip)]
+        pub tags: Vec<TagDB>,
+        /// Of the `photo_urls`, ordered by size. Ignored on writes, see
+        /// [`PetRepository::add_thumbnail`].
+        #[sqlx(skip)]
+        pub thumbnails: Vec<ThumbnailDB>,
+    }
+
+    impl PetDB {
+        pub(crate) fn price(&self) -> Option<Price> {
+            Some(Price {
+                amount: self.price? as u64,
+                currency: self.currency.clone()?,
+            })
+        }
+    }
+
+    /// A row of `pet_photos`.
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct PhotoDB {
+        pub id: i64,
+        pub pet_id: i64,
+        pub url: String,
+    }
+
+    /// A row of `photo_thumbnails`.
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct ThumbnailDB {
+        pub photo_url: String,
+        pub size: i64,
+        pub url: String,
+    }
+
+    /// How many pets share a status, category and size.
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct PetCount {
+        pub status: PetStatus,
+        pub category: Option<PetCategory>,
+        pub size: Option<PetSize>,
+        pub count: i64,
+    }
+
+    /// Which pets [`PetRepository::search`] returns, `None` matching any.
+    #[derive(Clone, Default, PartialEq, Debug)]
+    pub struct PetFilter {
+        pub status: Option<PetStatus>,
+        pub category: Option<PetCategory>,
+        pub size: Option<PetSize>,
+        pub tag: Option<String>,
+        /// Pets priced in this currency, or not priced at all.
+        pub currency: Option<String>,
+    }
+
+    impl PetFilter {
+        fn matches(&self, pet: &PetDB) -> bool {
+            let tag = |name: &String| pet.tags.iter().any(|tag| &tag.name == name);
+            self.status
+                .as_ref()
+                .is_none_or(|status| &pet.status == status)
+                && self
+                    .category
+                    .as_ref()
+                    .is_none_or(|category| pet.category.as_ref() == Some(category))
+                && self
+                    .size
+                    .as_ref()
+                    .is_none_or(|size| pet.size.as_ref() == Some(size))
+                && self.tag.as_ref().is_none_or(tag)
+                && self
+                    .currency
+                    .as_ref()
+                    .is_none_or(|currency| pet.currency.as_ref().is_none_or(|c| c == currency))
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum PetSortKey {
+        Id,
+        Category,
+        Status,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct PetSort {
+        pub key: PetSortKey,
+        pub descending: bool,
+    }
+
+    impl PetSort {
+        /// Parses `key` or `-key` for descending order.
+        pub fn parse(sort: &str) -> Option<PetSort> {
+            let (descending, key) = match sort.strip_prefix('-') {
+                Some(key) => (true, key),
+                None => (false, sort),
+            };
+            let key = match key {
+                "id" => PetSortKey::Id,
+                "category" => PetSortKey::Category,
+                "status" => PetSortKey::Status,
+                _ => return None,
+            };
+            Some(PetSort { key, descending })
+        }
+
+        /// The `order by` clause, pets sharing a category or status ordered
+        /// by id.
+        fn order_by(&self) -> String {
+            let direction = if self.descending { "desc" } else { "asc" };
+            match self.key {
+                PetSortKey::Id => format!("p.id {direction}"),
+                PetSortKey::Category => format!("p.category {direction}, p.id"),
+                PetSortKey::Status => format!("p.status {direction}, p.id"),
+            }
+        }
+
+        /// Orders like [`PetSort::order_by`] does in the database, which
+        /// compares categories and statuses by their stored names.
+        fn compare(&self, a: &PetDB, b: &PetDB) -> Ordering {
+            let ordering = match self.key {
+                PetSortKey::Id => a.id.cmp(&b.id),
+                PetSortKey::Category => stored_name(&a.category).cmp(&stored_name(&b.category)),
+                PetSortKey::Status => stored_name(&a.status).cmp(&stored_name(&b.status)),
+            };
+            let ordering = if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            ordering.then(a.id.cmp(&b.id))
+        }
+    }
+
+    /// The name sqlx stores an enum under, which is also its serde name.
+    fn stored_name<T: Serialize>(value: &T) -> Option<String> {
+        match serde_json::to_value(value) {
+            Ok(serde_json::Value::String(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    impl From<Pet> for PetDB {
+        fn from(pet: Pet) -> Self {
+            let mut tags: Vec<TagDB> = Vec::new();
+            for tag in pet.tags {
+                let name = tag.name.trim();
+                if !tags.iter().any(|tag| tag.name == name) {
+                    tags.push(TagDB {
+                        id: 0,
+                        name: name.to_string(),
+                    });
+                }
+            }
+            PetDB {
+                id: pet.id as i64,
+                category: Some(pet.category),
+                status: pet.status,
+                size: pet.size,
+                price: pet.price.as_ref().map(|price| price.amount as i64),
+                currency: pet.price.map(|price| price.currency),
+                photo_urls: pet.photo_urls,
+                tags,
+                thumbnails: Vec::new(),
+            }
+        }
+    }
+
+    impl TryFrom<PetDB> for Pet {
+        type Error = anyhow::Error;
+
+        fn try_from(pet: PetDB) -> Result<Self> {
+            let price = pet.price();
+            let Some(category) = pet.category else {
+                anyhow::bail!("pet {} has no category", pet.id);
+            };
+            let photos = pet
+                .photo_urls
+                .iter()
+                .map(|url| PetPhoto {
+                    url: url.clone(),
+                    thumbnails: pet
+                        .thumbnails
+                        .iter()
+                        .filter(|thumbnail| &thumbnail.photo_url == url)
+                        .map(|thumbnail| PetThumbnail {
+                            size: thumbnail.size as u32,
+                            url: thumbnail.url.clone(),
+                        })
+                        .collect(),
+                })
+                .collect();
+            Ok(Pet {
+                id: pet.id as u64,
+                category,
+                photo_urls: pet.photo_urls,
+                photos,
+                tags: pet.tags.into_iter().map(PetTag::from).collect(),
+                status: pet.status,
+                size: pet.size,
+                price,
+            })
+        }
+    }
+
+    #[async_trait]
+    pub trait PetRepository: Debug + Send + Sync {
+        /// Inserts a pet ignoring `pet.id` and returns the id assigned by the
+        /// database.
+        async fn create(&self, pet: PetDB) -> Result<i64>;
+        async fn get(&self, pet_id: u64) -> Result<Option<PetDB>>;
+        /// Returns `false` when there is no pet with `pet.id`. Leaves the
+        /// status alone, orders move it with [`update_status`](Self::update_status).
+        async fn update(&self, pet: PetDB) -> Result<bool>;
+        /// Moves the pet from `from` to `to`. Returns `false` without touching
+        /// anything when there is no such pet or it is no longer in `from`.
+        async fn update_status(&self, pet_id: u64, from: PetStatus, to: PetStatus) -> Result<bool>;
+        async fn delete(&self, id: u64) -> Result<()>;
+        /// Appends `url` to the pet's photos. Returns `false` when there is no
+        /// such pet.
+        async fn add_photo(&self, pet_id: u64, url: String) -> Result<bool>;
+        /// Records a thumbnail of the photo at `thumbnail.photo_url`, replacing
+        /// the one of the same size. Returns `false` without recording it when
+        /// no pet has that photo.
+        async fn add_thumbnail(&self, thumbnail: ThumbnailDB) -> Result<bool>;
+        /// Forgets the thumbnails of the photo at `photo_url`, returning them
+        /// so their files can be deleted too.
+        async fn delete_thumbnails(&self, photo_url: &str) -> Result<Vec<ThumbnailDB>>;
+        /// Pet counts grouped by status, category and size.
+        async fn count(&self) -> Result<Vec<PetCount>>;
+        /// Up to `limit` of the pets matching `filter` after skipping `offset`
+        /// of them, along with how many match in total.
+        async fn search(
+            &self,
+            filter: &PetFilter,
+            sort: PetSort,
+            limit: u64,
+            offset: u64,
+        ) -> Result<(Vec<PetDB>, u64)>;
+    }
+
+    /// Replaces the tags of pet `$pet_id` with `$tags` inside transaction
+    /// `$tx`, creating the tags not known by name yet.
+    macro_rules! link_tags {
+        ($tx:ident, $pet_id:expr, $tags:expr) => {
+            sqlx::query("delete from pet_tags where pet_id = $1;")
+                .bind($pet_id)
+                .execute(&mut *$tx)
+                .await?;
+            for tag in &$tags {
+                // the no-op update makes `returning` work for existing tags too
+                let (tag_id,): (i64,) = sqlx::query_as(
+                    "insert into tags (name) values ($1)
+                    on conflict (name) do update set name = excluded.name
+                    returning id;",
+                )
+                .bind(&tag.name)
+                .fetch_one(&mut *$tx)
+                .await?;
+                sqlx::query("insert into pet_tags (pet_id, tag_id) values ($1, $2);")
+                    .bind($pet_id)
+                    .bind(tag_id)
+                    .execute(&mut *$tx)
+                    .await?;
+            }
+        };
+    }
+
+    /// Replaces the photos of pet `$pet_id` with `$urls` inside transaction
+    /// `$tx`.
+    macro_rules! link_photos {
+        ($tx:ident, $pet_id:expr, $urls:expr) => {
+            sqlx::query("delete from pet_photos where pet_id = $1;")
+                .bind($pet_id)
+                .execute(&mut *$tx)
+                .await?;
+            for url in &$urls {
+                sqlx::query("insert into pet_photos (pet_id, url) values ($1, $2);")
+                    .bind($pet_id)
+                    .bind(url)
+                    .execute(&mut *$tx)
+                    .await?;
+            }
+        };
+    }
+
+    /// Fills in the `photo_urls`, `thumbnails` and `tags` of `pets`.
+    async fn load_related(db: &Sql, pets: &mut [PetDB]) -> Result<()> {
+        if pets.is_empty() {
+            return Ok(());
+        }
+        let select_photos = format!(
+            "select ph.pet_id, ph.url
+            from pet_photos ph
+            where ph.pet_id in ({})
+            order by ph.id",
+            in_params(pets.len())
+        );
+        let select_thumbnails = format!(
+            "select distinct ph.pet_id, th.photo_url, th.size, th.url
+            from pet_photos ph
+            join photo_thumbnails th on th.photo_url = ph.url
+            where ph.pet_id in ({})
+            order by th.size",
+            in_params(pets.len())
+        );
+        let select_tags = format!(
+            "select pt.pet_id, t.id, t.name
+            from pet_tags pt
+            join tags t on t.id = pt.tag_id
+            where pt.pet_id in ({})
+            order by t.name",
+            in_params(pets.len())
+        );
+
+        let photos: Vec<(i64, String)> = on_conn!(db, |conn| {
+            let mut query = sqlx::query_as(&select_photos);
+            for pet in pets.iter() {
+                query = query.bind(pet.id);
+            }
+            query.fetch_all(&mut *conn).await
+        })?;
+        let thumbnails: Vec<(i64, String, i64, String)> = on_conn!(db, |conn| {
+            let mut query = sqlx::query_as(&select_thumbnails);
+            for pet in pets.iter() {
+                query = query.bind(pet.id);
+            }
+            query.fetch_all(&mut *conn).await
+        })?;
+        let tags: Vec<(i64, i64, String)> = on_conn!(db, |conn| {
+            let mut query = sqlx::query_as(&select_tags);
+            for pet in pets.iter() {
+                query = query.bind(pet.id);
+            }
+            query.fetch_all(&mut *conn).await
+        })?;
+        for (pet_id, url) in photos {
+            if let Some(pet) = pets.iter_mut().find(|pet| pet.id == pet_id) {
+                pet.photo_urls.push(url);
+            }
+        }
+        for (pet_id, photo_url, size, url) in thumbnails {
+            if let Some(pet) = pets.iter_mut().find(|pet| pet.id == pet_id) {
+                pet.thumbnails.push(ThumbnailDB {
+                    photo_url,
+                    size,
+                    url,
+                });
+            }
+        }
+        for (pet_id, id, name) in tags {
+            if let Some(pet) = pets.iter_mut().find(|pet| pet.id == pet_id) {
+                pet.tags.push(TagDB { id, name });
+            }
+        }
+        Ok(())
+    }
+
+    #[async_trait]
+    impl PetRepository for Sql {
+        #[tracing::instrument(skip(self))]
+        async fn create(&self, pet: PetDB) -> Result<i64> {
+            let id = on_conn!(self, |conn| {
+                let mut tx = conn.begin().await?;
+                let (id,): (i64,) = sqlx::query_as(
+                    "insert into pets (category, status, size, price, currency)
+                    values ($1, $2, $3, $4, $5)
+                    returning id;",
+                )
+                .bind(pet.category)
+                .bind(pet.status)
+                .bind(pet.size)
+                .bind(pet.price)
+                .bind(pet.currency)
+                .fetch_one(&mut *tx)
+                .await?;
+                link_photos!(tx, id, pet.photo_urls);
+                link_tags!(tx, id, pet.tags);
+                tx.commit().await?;
+                id
+            });
+
+            Ok(id)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, pet_id: u64) -> Result<Option<PetDB>> {
+            let res: Option<PetDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from pets p
+                    where p.id = $1",
+                )
+                .bind(pet_id as i64)
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+
+            let mut res = Vec::from_iter(res);
+            load_related(self, &mut res).await?;
+            Ok(res.pop())
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update(&self, pet: PetDB) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                let mut tx = conn.begin().await?;
+                let rows_affected = sqlx::query(
+                    "update pets set
+                        category = $2,
+                        size = $3,
+                        price = $4,
+                        currency = $5
+                    where id = $1;",
+                )
+                .bind(pet.id)
+                .bind(pet.category)
+                .bind(pet.size)
+                .bind(pet.price)
+                .bind(pet.currency)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+                if rows_affected > 0 {
+                    link_photos!(tx, pet.id, pet.photo_urls);
+                    link_tags!(tx, pet.id, pet.tags);
+                }
+                tx.commit().await?;
+                rows_affected
+            });
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update_status(&self, pet_id: u64, from: PetStatus, to: PetStatus) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query("update pets set status = $3 where id = $1 and status = $2;")
+                    .bind(pet_id as i64)
+                    .bind(from)
+                    .bind(to)
+                    .execute(&mut *conn)
+                    .await
+                    .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn delete(&self, id: u64) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query("delete from pets where id = $1")
+                    .bind(id as i64)
+                    .execute(&mut *conn)
+                    .await?;
+            });
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn add_photo(&self, pet_id: u64, url: String) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query(
+                    "insert into pet_photos (pet_id, url)
+                    select p.id, $2 from pets p where p.id = $1;",
+                )
+                .bind(pet_id as i64)
+                .bind(url)
+                .execute(&mut *conn)
+                .await
+                .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn add_thumbnail(&self, thumbnail: ThumbnailDB) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query(
+                    "insert into photo_thumbnails (photo_url, size, url)
+                    select $1, $2, $3
+                    where exists (select 1 from pet_photos ph where ph.url = $1)
+                    on conflict (photo_url, size) do update set url = excluded.url;",
+                )
+                .bind(thumbnail.photo_url)
+                .bind(thumbnail.size)
+                .bind(thumbnail.url)
+                .execute(&mut *conn)
+                .await
+                .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn delete_thumbnails(&self, photo_url: &str) -> Result<Vec<ThumbnailDB>> {
+            let res: Vec<ThumbnailDB> = on_conn!(self, |conn| {
+                sqlx::query_as("delete from photo_thumbnails where photo_url = $1 returning *;")
+                    .bind(photo_url)
+                    .fetch_all(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn count(&self) -> Result<Vec<PetCount>> {
+            let res: Vec<PetCount> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select p.status, p.category, p.size, count(*) as count
+                    from pets p
+                    group by p.status, p.category, p.size",
+                )
+                .fetch_all(&mut *conn)
+                .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn search(
+            &self,
+            filter: &PetFilter,
+            sort: PetSort,
+            limit: u64,
+            offset: u64,
+        ) -> Result<(Vec<PetDB>, u64)> {
+            let filters = "where ($1 is null or p.status = $1)
+                and ($2 is null or p.category = $2)
+                and ($3 is null or exists (
+                    select 1 from pet_tags pt
+                    join tags t on t.id = pt.tag_id
+                    where pt.pet_id = p.id and t.name = $3
+                ))
+                and ($4 is null or p.size = $4)
+                and ($5 is null or p.currency is null or p.currency = $5)";
+            let count = format!("select count(*) from pets p {filters}");
+            let select = format!(
+                "select * from pets p {filters}
+                order by {}
+                limit $6 offset $7",
+                sort.order_by()
+            );
+
+            let (total,): (i64,) = on_conn!(self, |conn| {
+                sqlx::query_as(&count)
+                    .bind(filter.status.clone())
+                    .bind(filter.category.clone())
+                    .bind(filter.tag.clone())
+                    .bind(filter.size.clone())
+                    .bind(filter.currency.clone())
+                    .fetch_one(&mut *conn)
+                    .await
+            })?;
+            let mut pets: Vec<PetDB> = on_conn!(self, |conn| {
+                sqlx::query_as(&select)
+                    .bind(filter.status.clone())
+                    .bind(filter.category.clone())
+                    .bind(filter.tag.clone())
+                    .bind(filter.size.clone())
+                    .bind(filter.currency.clone())
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(&mut *conn)
+                    .await
+            })?;
+
+            load_related(self, &mut pets).await?;
+            Ok((pets, total as u64))
+        }
+    }
+
+    /// What [`link_tags!`] does in the database.
+    fn link_memory_tags(tables: &mut Tables, pet_id: i64, tags: &[TagDB]) {
+        tables.pet_tags.retain(|&(pet, _)| pet != pet_id);
+        for tag in tags {
+            let known = tables.tags.values().find(|known| known.name == tag.name);
+            let tag_id = match known {
+                Some(known) => known.id,
+                None => {
+                    let id = tables.next_id();
+                    let name = tag.name.clone();
+                    tables.tags.insert(id, TagDB { id, name });
+                    id
+                }
+            };
+            tables.pet_tags.insert((pet_id, tag_id));
+        }
+    }
+
+    /// What [`link_photos!`] does in the database.
+    fn link_memory_photos(tables: &mut Tables, pet_id: i64, urls: &[String]) {
+        tables.pet_photos.retain(|_, photo| photo.pet_id != pet_id);
+        for url in urls {
+            let id = tables.next_id();
+            let url = url.clone();
+            tables.pet_photos.insert(id, PhotoDB { id, pet_id, url });
+        }
+    }
+
+    /// The stored pet with its photos, thumbnails and tags filled in.
+    fn with_related(tables: &Tables, pet: &PetDB) -> PetDB {
+        let photo_urls: Vec<String> = tables
+            .pet_photos
+            .values()
+            .filter(|photo| photo.pet_id == pet.id)
+            .map(|photo| photo.url.clone())
+            .collect();
+        let mut thumbnails: Vec<ThumbnailDB> = tables
+            .photo_thumbnails
+            .values()
+            .filter(|thumbnail| photo_urls.contains(&thumbnail.photo_url))
+            .cloned()
+            .collect();
+        thumbnails.sort_by_key(|thumbnail| thumbnail.size);
+        let mut tags: Vec<TagDB> = tables
+            .pet_tags
+            .iter()
+            .filter(|&&(pet_id, _)| pet_id == pet.id)
+            .filter_map(|(_, tag_id)| tables.tags.get(tag_id).cloned())
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        PetDB {
+            photo_urls,
+            tags,
+            thumbnails,
+            ..pet.clone()
+        }
+    }
+
+    #[async_trait]
+    impl PetRepository for MemoryDb {
+        async fn create(&self, pet: PetDB) -> Result<i64> {
+            let mut tables = self.lock();
+            let id = tables.next_id();
+            link_memory_photos(&mut tables, id, &pet.photo_urls);
+            link_memory_tags(&mut tables, id, &pet.tags);
+            let (photo_urls, tags) = (Vec::new(), Vec::new());
+            let pet = PetDB {
+                id,
+                photo_urls,
+                tags,
+                ..pet
+            };
+            tables.pets.insert(id, pet);
+            Ok(id)
+        }
+
+        async fn get(&self, pet_id: u64) -> Result<Option<PetDB>> {
+            let tables = self.lock();
+            let pet = tables.pets.get(&(pet_id as i64));
+            Ok(pet.map(|pet| with_related(&tables, pet)))
+        }
+
+        async fn update(&self, pet: PetDB) -> Result<bool> {
+            let mut tables = self.lock();
+            let Some(status) = tables.pets.get(&pet.id).map(|p| p.status.clone()) else {
+                return Ok(false);
+            };
+            link_memory_photos(&mut tables, pet.id, &pet.photo_urls);
+            link_memory_tags(&mut tables, pet.id, &pet.tags);
+            let (photo_urls, tags) = (Vec::new(), Vec::new());
+            let pet = PetDB {
+                photo_urls,
+                tags,
+                status,
+                ..pet
+            };
+            tables.pets.insert(pet.id, pet);
+            Ok(true)
+        }
+
+        async fn update_status(&self, pet_id: u64, from: PetStatus, to: PetStatus) -> Result<bool> {
+            let mut tables = self.lock();
+            let pet = tables
+                .pets
+                .get_mut(&(pet_id as i64))
+                .filter(|pet| pet.status == from);
+            Ok(pet.map(|pet| pet.status = to).is_some())
+        }
+
+        async fn delete(&self, id: u64) -> Result<()> {
+            let mut tables = self.lock();
+            let id = id as i64;
+            if tables
+                .order_items
+                .values()
+                .any(|item| item.pet_id == Some(id))
+            {
+                return Err(foreign_key_violation("order_items_pet_id_fkey"));
+            }
+            tables.pets.remove(&id);
+            tables.pet_tags.retain(|&(pet, _)| pet != id);
+            tables.pet_photos.retain(|_, photo| photo.pet_id != id);
+            tables.cart_items.retain(|_, item| item.pet_id != Some(id));
+            Ok(())
+        }
+
+        async fn add_photo(&self, pet_id: u64, url: String) -> Result<bool> {
+            let mut tables = self.lock();
+            let pet_id = pet_id as i64;
+            if !tables.pets.contains_key(&pet_id) {
+                return Ok(false);
+            }
+            let id = tables.next_id();
+            tables.pet_photos.insert(id, PhotoDB { id, pet_id, url });
+            Ok(true)
+        }
+
+        async fn add_thumbnail(&self, thumbnail: ThumbnailDB) -> Result<bool> {
+            let mut tables = self.lock();
+            let url = &thumbnail.photo_url;
+            if !tables.pet_photos.values().any(|photo| &photo.url == url) {
+                return Ok(false);
+            }
+            let key = (url.clone(), thumbnail.size);
+            tables.photo_thumbnails.insert(key, thumbnail);
+            Ok(true)
+        }
+
+        async fn delete_thumbnails(&self, photo_url: &str) -> Result<Vec<ThumbnailDB>> {
+            let mut tables = self.lock();
+            let (deleted, kept) = std::mem::take(&mut tables.photo_thumbnails)
+                .into_iter()
+                .partition(|((url, _), _)| url == photo_url);
+            tables.photo_thumbnails = kept;
+            Ok(deleted.into_values().collect())
+        }
+
+        async fn count(&self) -> Result<Vec<PetCount>> {
+            let mut counts: Vec<PetCount> = Vec::new();
+            for pet in self.lock().pets.values() {
+                let group = counts.iter_mut().find(|c| {
+                    c.status == pet.status && c.category == pet.category && c.size == pet.size
+                });
+                match group {
+                    Some(group) => group.count += 1,
+                    None => counts.push(PetCount {
+                        status: pet.status.clone(),
+                        category: pet.category.clone(),
+                        size: pet.size.clone(),
+                        count: 1,
+                    }),
+                }
+            }
+            Ok(counts)
+        }
+
+        async fn search(
+            &self,
+            filter: &PetFilter,
+            sort: PetSort,
+            limit: u64,
+            offset: u64,
+        ) -> Result<(Vec<PetDB>, u64)> {
+            let tables = self.lock();
+            let mut pets: Vec<PetDB> = tables
+                .pets
+                .values()
+                .map(|pet| with_related(&tables, pet))
+                .filter(|pet| filter.matches(pet))
+                .collect();
+            pets.sort_by(|a, b| sort.compare(a, b));
+            let total = pets.len() as u64;
+            let page = pets
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect();
+            Ok((page, total))
+        }
+    }
+}
+
+pub mod api {
+    use axum::{
+        extract::DefaultBodyLimit,
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+
+    use crate::{
+        auth::{self, Permission},
+        AppState,
+    };
+
+    use super::service;
+
+    /// Room for what an upload carries besides the photo: the multipart
+    /// boundaries, the part headers and any other fields.
+    const UPLOAD_HEADROOM: usize = 64 * 1024;
+
+    /// Photo uploads may be up to `max_upload_bytes`, plus their framing.
+    pub(crate) fn create_router(max_upload_bytes: usize) -> Router<AppState> {
+        let edit_pets = middleware::from_fn(auth::require(Permission::EditPets));
+
+        Router::new()
+            .route(
+                "/",
+                get(service::find_pets)
+                    .merge(post(service::create_pet).route_layer(edit_pets.clone())),
+            )
+            .route(
+                "/:pet_id",
+                get(service::get_pet).merge(
+                    post(service::update_pet)
+                        .delete(service::delete)
+                        .route_layer(edit_pets.clone()),
+                ),
+            )
+            .route(
+                "/:pet_id/photos",
+                post(service::upload_photo)
+                    .route_layer(edit_pets)
+                    .layer(DefaultBodyLimit::max(max_upload_bytes + UPLOAD_HEADROOM)),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod storage {
+        use crate::{
+            pet::{
+                storage::{PetDB, PetFilter, PetSort},
+                Pet, PetCategory, PetSize, PetStatus, PetTag, Price,
+            },
+            AppState,
+        };
+
+        fn test_pet() -> Pet {
+            Pet {
+                id: 0,
+                category: PetCategory::Feline,
+                photo_urls: vec!["https://example.com/cat.png".to_string()],
+                photos: Vec::new(),
+                tags: vec![tag("fluffy"), tag("hypoallergenic")],
+                status: PetStatus::Available,
+                size: Some(PetSize::House),
+                price: Some(Price {
+                    amount: 1250,
+                    currency: "EUR".to_string(),
+                }),
+            }
+        }
+
+        fn tag(name: &str) -> PetTag {
+            PetTag {
+                id: 0,
+                name: name.to_string(),
+            }
+        }
+
+        #[test]
+        fn validate_price() {
+            use crate::validation::Validate;
+
+            assert!(test_pet().validate().is_empty());
+            let errors = Pet {
+                price: Some(Price {
+                    amount: u64::MAX,
+                    currency: "eur".to_string(),
+                }),
+                ..test_pet()
+            }
+            .validate();
+            assert_eq!(
+                vec!["price.currency", "price.amount"],
+                errors.iter().map(|e| e.field).collect::<Vec<_>>()
+            );
+        }
+
+        #[test]
+        fn validate_search() {
+            use axum::{extract::Query, http::Uri};
+
+            use crate::{pet::PetSearch, validation::Validate};
+
+            let search = |query: &str| {
+                let uri: Uri = format!("/pets?{query}").parse().unwrap();
+                Query::<PetSearch>::try_from_uri(&uri).unwrap().0
+            };
+            assert!(search("page=2&per_page=50").validate().is_empty());
+            for query in ["page=0", "page=18446744073709551615", "per_page=101"] {
+                assert_eq!(1, search(query).validate().len(), "{query}");
+            }
+        }
+
+        /// The pet as read back, without the tag ids assigned by the database
+        /// and the `photos` derived from its `photo_urls`.
+        fn read_back(pet: Option<PetDB>) -> anyhow::Result<Pet> {
+            let mut pet = Pet::try_from(pet.expect("the pet exists"))?;
+            pet.tags.iter_mut().for_each(|tag| tag.id = 0);
+            pet.photos.clear();
+            Ok(pet)
+        }
+
+        #[tokio::test]
+        async fn get_missing_pet() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let res = state.pets.get(0).await?;
+
+                assert_eq!(None, res);
+
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn insert_pet() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let id = state.pets.create(PetDB::from(test_pet())).await?;
+                let get_res = state.pets.get(id as u64).await?;
+
+                assert_eq!(
+                    Pet {
+                        id: id as u64,
+                        ..test_pet()
+                    },
+                    read_back(get_res)?
+                );
+
+                state.pets.delete(id as u64).await?;
+                assert_eq!(None, state.pets.get(id as u64).await?);
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn update_pet() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let id = state.pets.create(PetDB::from(test_pet())).await?;
+                let sold = Pet {
+                    id: id as u64,
+                    status: PetStatus::Sold,
+                    tags: vec![tag("odourless"), tag("hypoallergenic"), tag("odourless")],
+                    ..test_pet()
+                };
+                let updated = state.pets.update(PetDB::from(sold.clone())).await?;
+                let result = state.pets.get(id as u64).await?;
+
+                assert!(updated);
+                assert_eq!(
+                    Pet {
+                        tags: vec![tag("hypoallergenic"), tag("odourless")],
+                        status: test_pet().status,
+                        ..sold.clone()
+                    },
+                    read_back(result)?
+                );
+
+                let odourless = state.pets.get(id as u64).await?.unwrap().tags[1].id;
+                state.tags.delete(odourless as u64).await?;
+                assert_eq!(
+                    vec![tag("hypoallergenic")],
+                    read_back(state.pets.get(id as u64).await?)?.tags
+                );
+
+                let missing = state
+                    .pets
+                    .update(PetDB {
+                        id: -1,
+                        ..PetDB::from(sold)
+                    })
+                    .await?;
+                assert!(!missing);
+
+                state.pets.delete(id as u64).await?;
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn search_pets() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let dog = Pet {
+                    category: PetCategory::Canine,
+                    ..test_pet()
+                };
+                let sold = Pet {
+                    status: PetStatus::Sold,
+                    ..test_pet()
+                };
+                let plain = Pet {
+                    tags: vec![tag("fluffy")],
+                    ..test_pet()
+                };
+                let mut ids = Vec::new();
+                for pet in [test_pet(), dog, sold, plain, test_pet()] {
+                    ids.push(state.pets.create(PetDB::from(pet)).await?);
+                }
+                let search = |filter: PetFilter, sort: &str, limit: u64, offset: u64| {
+                    let pets = state.pets.clone();
+                    let sort = PetSort::parse(sort).unwrap();
+                    async move {
+                        let (found, total) = pets.search(&filter, sort, limit, offset).await?;
+                        let found: Vec<i64> = found.into_iter().map(|pet| pet.id).collect();
+                        anyhow::Ok((found, total))
+                    }
+                };
+
+                let feline = PetFilter {
+                    status: Some(PetStatus::Available),
+                    category: Some(PetCategory::Feline),
+                    size: Some(PetSize::House),
+                    tag: Some("hypoallergenic".to_string()),
+                    currency: None,
+                };
+                assert_eq!(
+                    (vec![ids[4], ids[0]], 2),
+                    search(feline.clone(), "-id", 10, 0).await?
+                );
+                assert_eq!((vec![ids[4]], 2), search(feline, "id", 1, 1).await?);
+                assert_eq!(
+                    (vec![ids[1], ids[0]], 5),
+                    search(PetFilter::default(), "category", 2, 0).await?
+                );
+                assert_eq!(
+                    (vec![ids[2]], 5),
+                    search(PetFilter::default(), "-status", 1, 0).await?
+                );
+                assert_eq!(2, state.tags.list().await?.len());
+
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+    }
+
+    mod api {
+        use axum::{
+            body::{to_bytes, Body},
+            http::{header, StatusCode},
+        };
+        use serde_json::{json, Value};
+
+        use crate::{
+            auth::Role,
+            pet::api,
+            testing::{app, register, request, send},
+            AppState,
+        };
+
+        #[tokio::test]
+        async fn require_permissions() -> anyhow::Result<()> {
+            let state = AppState::in_memory();
+            let customer_id = register(&state, Role::Customer).await?;
+            let staff_id = register(&state, Role::Staff).await?;
+            let pets = || {
+                let routes = api::create_router(state.media.max_upload_bytes);
+                app(&state, "/pets", routes)
+            };
+            let pet = json!({"category": "Feline", "status": "available"});
+
+            let anonymous = request(&state, "GET", "/pets", None, None)?;
+            assert_eq!(StatusCode::OK, send(pets(), anonymous).await?.status());
+            let by_customer = request(
+                &state,
+                "POST",
+                "/pets",
+                Some(customer_id),
+                Some(pet.clone()),
+            )?;
+            assert_eq!(
+                StatusCode::FORBIDDEN,
+                send(pets(), by_customer).await?.status()
+            );
+            let by_staff = request(&state, "POST", "/pets", Some(staff_id), Some(pet))?;
+            assert_eq!(StatusCode::CREATED, send(pets(), by_staff).await?.status());
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn limit_uploads() -> anyhow::Result<()> {
+            let state = AppState::in_memory();
+            let staff_id = register(&state, Role::Staff).await?;
+            let pets = || {
+                let routes = api::create_router(state.media.max_upload_bytes);
+                app(&state, "/pets", routes)
+            };
+            let pet = json!({"category": "Feline", "status": "available"});
+            let created = request(&state, "POST", "/pets", Some(staff_id), Some(pet))?;
+            let created = to_bytes(send(pets(), created).await?.into_body(), usize::MAX).await?;
+            let pet_id = serde_json::from_slice::<Value>(&created)?["id"].clone();
+
+            // fields other than the photo are skipped, yet count against the body
+            let boundary = "pet-photo-boundary";
+            let mut body = format!(
+                "--{boundary}\r\n\
+                Content-Disposition: form-data; name=\"notes\"\r\n\r\n"
+            )
+            .into_bytes();
+            body.resize(body.len() + 4 * state.media.max_upload_bytes, b'a');
+            body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+            let mut upload = request(
+                &state,
+                "POST",
+                &format!("/pets/{pet_id}/photos"),
+                Some(staff_id),
+                None,
+            )?;
+            upload.headers_mut().insert(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={boundary}").parse()?,
+            );
+            *upload.body_mut() = Body::from(body);
+            assert_eq!(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                send(pets(), upload).await?.status()
+            );
+            Ok(())
+        }
+    }
+
+    mod service {
+        use axum::{
+            body::{to_bytes, Body, Bytes},
+            extract::{FromRequest, Multipart, Path, State},
+            http::{header, Request, StatusCode},
+            response::IntoResponse,
+        };
+        use image::{DynamicImage, ImageFormat};
+        use serde_json::{json, Value};
+
+        use crate::{
+            auth::{AuthUser, Role},
+            media,
+            pet::{service, storage::PetDB, PetCategory, PetStatus},
+            validation::Valid,
+            AppState,
+        };
+
+        const BOUNDARY: &str = "pet-photo-boundary";
+
+        /// A multipart body carrying `bytes` as file field `field`.
+        async fn multipart(field: &str, bytes: &[u8]) -> anyhow::Result<Multipart> {
+            let mut body = format!(
+                "--{BOUNDARY}\r\n\
+                Content-Disposition: form-data; name=\"{field}\"; filename=\"cat.png\"\r\n\
+                Content-Type: image/png\r\n\r\n"
+            )
+            .into_bytes();
+            body.extend_from_slice(bytes);
+            body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+            let request = Request::builder()
+                .method("POST")
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={BOUNDARY}"),
+                )
+                .body(Body::from(body))?;
+
+            Multipart::from_request(request, &())
+                .await
+                .map_err(|rejection| anyhow::anyhow!(rejection.body_text()))
+        }
+
+        async fn upload(
+            state: &AppState,
+            pet_id: u64,
+            field: &str,
+            bytes: &[u8],
+        ) -> anyhow::Result<(StatusCode, Value)> {
+            let multipart = Ok(multipart(field, bytes).await?);
+            let response = service::upload_photo(State(state.clone()), Path(pet_id), multipart)
+                .await
+                .into_response();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await?;
+            Ok((status, serde_json::from_slice(&body)?))
+        }
+
+        #[tokio::test]
+        async fn update_unknown_pet() -> anyhow::Result<()> {
+            let state = AppState::in_memory();
+            let pet_id = state
+                .pets
+                .create(PetDB {
+                    id: 0,
+                    category: Some(PetCategory::Feline),
+                    status: PetStatus::Available,
+                    size: None,
+                    price: None,
+                    currency: None,
+                    photo_urls: Vec::new(),
+                    tags: Vec::new(),
+                    thumbnails: Vec::new(),
+                })
+                .await? as u64;
+            let staff = AuthUser {
+                id: 1,
+                username: "staff".to_string(),
+                role: Role::Staff,
+            };
+            let dog = serde_json::from_value(json!({"category": "Canine", "status": "available"}))?;
+
+            // would be `pet_id` again if the id were cut down to 32 bits
+            let res = service::update_pet(
+                State(state.clone()),
+                staff,
+                Path(pet_id + (1 << 32)),
+                Valid(dog),
+            )
+            .await
+            .into_response();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+            assert_eq!(
+                Some(PetCategory::Feline),
+                state.pets.get(pet_id).await?.unwrap().category
+            );
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn upload_photos() -> anyhow::Result<()> {
+            let mut png = std::io::Cursor::new(Vec::new());
+            DynamicImage::new_rgb8(640, 160).write_to(&mut png, ImageFormat::Png)?;
+            let png = png.into_inner();
+            let state = AppState::in_memory();
+            let pet_id = state
+                .pets
+                .create(PetDB {
+                    id: 0,
+                    category: Some(PetCategory::Feline),
+                    status: PetStatus::Available,
+                    size: None,
+                    price: None,
+                    currency: None,
+                    photo_urls: vec!["https://example.com/cat.png".to_string()],
+                    tags: Vec::new(),
+                    thumbnails: Vec::new(),
+                })
+                .await? as u64;
+
+            let (status, pet) = upload(&state, pet_id, "photo", &png).await?;
+            assert_eq!(StatusCode::CREATED, status);
+            assert_eq!("https://example.com/cat.png", pet["photo_urls"][0]);
+            let key = media::key(pet["photo_urls"][1].as_str().unwrap_or_default())
+                .expect("uploads are served under /media");
+            assert!(key.starts_with(&format!("pets/{pet_id}/")));
+            assert!(key.ends_with(".png"));
+            assert_eq!(
+                Some(Bytes::from(png.clone())),
+                state.media.blobs.get(key).await?
+            );
+
+            // rendered in the background
+            let mut thumbnails = Vec::new();
+            for _ in 0..100 {
+                let pet = state.pets.get(pet_id).await?.expect("the pet exists");
+                thumbnails = pet.thumbnails;
+                if thumbnails.len() == 2 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            let sizes: Vec<i64> = thumbnails.iter().map(|thumbnail| thumbnail.size).collect();
+            assert_eq!(vec![128, 512], sizes);
+            let thumbnail_key = media::key(&thumbnails[0].url).expect("served under /media");
+            assert!(state.media.blobs.get(thumbnail_key).await?.is_some());
+
+            let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+            let too_large = [&png[..], &vec![0; state.media.max_upload_bytes]].concat();
+            for (expected, pet_id, field, bytes) in [
+                (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    pet_id,
+                    "photo",
+                    &svg[..],
+                ),
+                (StatusCode::PAYLOAD_TOO_LARGE, pet_id, "photo", &too_large),
+                (StatusCode::UNPROCESSABLE_ENTITY, pet_id, "picture", &png),
+                (StatusCode::NOT_FOUND, 0, "photo", &png),
+            ] {
+                assert_eq!(expected, upload(&state, pet_id, field, bytes).await?.0);
+            }
+            let pet = state.pets.get(pet_id).await?.expect("the pet exists");
+            assert_eq!(2, pet.photo_urls.len());
+
+            let deleted = service::delete(State(state.clone()), Path(pet_id))
+                .await
+                .into_response();
+            assert_eq!(StatusCode::OK, deleted.status());
+            assert_eq!(None, state.media.blobs.get(key).await?);
+            assert_eq!(None, state.media.blobs.get(thumbnail_key).await?);
+            Ok(())
+        }
+    }
 }
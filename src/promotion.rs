@@ -0,0 +1,1012 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::FieldError,
+    pet::{is_currency_code, PetCategory},
+    validation::{Errors, Validate},
+};
+
+/// A discount on the orders its `rule` picks, e.g. 10% off all Rodents. Given
+/// on every such order, or only on those entering its `code`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Promotion {
+    /// Assigned by the server, whatever the client sends is ignored.
+    #[serde(default)]
+    id: u64,
+    /// The coupon code, matched ignoring case and stored upper case.
+    #[serde(default)]
+    code: Option<String>,
+    rule: PromotionRule,
+    discount: Discount,
+    /// Of the amounts in `rule` and `discount`, required when they have one.
+    /// Only orders priced in it qualify for them.
+    #[serde(default)]
+    currency: Option<String>,
+    /// When the promotion runs, open ended on either side when unset.
+    #[serde(default)]
+    starts_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    ends_at: Option<DateTime<Utc>>,
+    /// How many orders may get the discount, unlimited when unset.
+    #[serde(default)]
+    max_uses: Option<u64>,
+    /// How many orders got it so far. Ignored on input.
+    #[serde(default, skip_deserializing)]
+    uses: u64,
+}
+
+/// Which orders qualify for a promotion, and for which of their pets.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromotionRule {
+    /// Orders with at least `min_quantity` pets of the `category`, for those.
+    Category {
+        category: PetCategory,
+        #[serde(default = "one")]
+        min_quantity: u64,
+    },
+    /// Orders with at least `min_quantity` pets carrying the `tag`, for those.
+    Tag {
+        tag: String,
+        #[serde(default = "one")]
+        min_quantity: u64,
+    },
+    /// Orders with a subtotal of at least `min_subtotal`, for all their pets.
+    OrderTotal { min_subtotal: u64 },
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Discount {
+    /// Off the price of the pets the rule picks.
+    Percent { percent: u64 },
+    /// Off the order, at most the price of the pets the rule picks.
+    Amount { amount: u64 },
+    /// The order ships for free, its prices stay as they are.
+    FreeShipping,
+}
+
+/// Coupon codes are matched ignoring case and surrounding blanks.
+pub(crate) fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+/// A line of an order as promotions see it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OrderLine {
+    /// Of the pet, or of the kind of pet ordered.
+    pub category: Option<PetCategory>,
+    /// Names of the tags of the pet, none before one is allocated.
+    pub tags: Vec<String>,
+    pub quantity: u64,
+    /// The price of the line, `0` while it has none.
+    pub amount: u64,
+}
+
+/// What a promotion takes off an order, see [`Promotion::apply`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AppliedDiscount {
+    pub promotion_id: u64,
+    pub code: Option<String>,
+    pub amount: u64,
+    pub free_shipping: bool,
+}
+
+impl Promotion {
+    /// Whether the promotion runs at `now` and has uses left.
+    fn is_running(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at.is_none_or(|at| at <= now)
+            && self.ends_at.is_none_or(|at| now < at)
+            && self.max_uses.is_none_or(|max| self.uses < max)
+    }
+
+    /// The discount on an order of `lines` priced in `currency`, `None` when
+    /// the order does not qualify. Percentages are rounded half up to the
+    /// minor unit.
+    pub(crate) fn apply(
+        &self,
+        lines: &[OrderLine],
+        currency: Option<&str>,
+    ) -> Option<AppliedDiscount> {
+        let picked: Vec<&OrderLine> = match &self.rule {
+            PromotionRule::Category { category, .. } => lines
+                .iter()
+                .filter(|line| line.category.as_ref() == Some(category))
+                .collect(),
+            PromotionRule::Tag { tag, .. } => lines
+                .iter()
+                .filter(|line| line.tags.contains(tag))
+                .collect(),
+            PromotionRule::OrderTotal { .. } => lines.iter().collect(),
+        };
+        let quantity: u64 = picked.iter().map(|line| line.quantity).sum();
+        let price = picked
+            .iter()
+            .map(|line| line.amount)
+            .fold(0u64, u64::saturating_add);
+        let same_currency = self.currency.is_some() && self.currency.as_deref() == currency;
+        let qualifies = match &self.rule {
+            PromotionRule::Category { min_quantity, .. }
+            | PromotionRule::Tag { min_quantity, .. } => quantity >= *min_quantity,
+            PromotionRule::OrderTotal { min_subtotal } => same_currency && price >= *min_subtotal,
+        };
+        let amount = match self.discount {
+            _ if !qualifies => return None,
+            Discount::Percent { percent } => ((price as u128 * percent as u128 + 50) / 100) as u64,
+            Discount::Amount { .. } if !same_currency => return None,
+            Discount::Amount { amount } => amount.min(price),
+            Discount::FreeShipping => 0,
+        };
+        Some(AppliedDiscount {
+            promotion_id: self.id,
+            code: self.code.clone(),
+            amount,
+            free_shipping: self.discount == Discount::FreeShipping,
+        })
+    }
+}
+
+impl Validate for Promotion {
+    fn validate(&self) -> Vec<FieldError> {
+        let min_quantity = match &self.rule {
+            PromotionRule::Category { min_quantity, .. }
+            | PromotionRule::Tag { min_quantity, .. } => *min_quantity,
+            PromotionRule::OrderTotal { .. } => 1,
+        };
+        let min_subtotal = match self.rule {
+            PromotionRule::OrderTotal { min_subtotal } => min_subtotal,
+            _ => 0,
+        };
+        let percent = match self.discount {
+            Discount::Percent { percent } => percent,
+            _ => 100,
+        };
+        let amount = match self.discount {
+            Discount::Amount { amount } => amount,
+            _ => 1,
+        };
+        let needs_currency = matches!(self.rule, PromotionRule::OrderTotal { .. })
+            || matches!(self.discount, Discount::Amount { .. });
+        Errors::default()
+            .check(
+                self.code
+                    .as_ref()
+                    .is_none_or(|code| !code.trim().is_empty()),
+                "code",
+                "must not be empty",
+            )
+            .check(
+                !matches!(&self.rule, PromotionRule::Tag { tag, .. } if tag.trim().is_empty()),
+                "rule.tag",
+                "must not be empty",
+            )
+            .check(min_quantity >= 1, "rule.min_quantity", "must be at least 1")
+            .check(
+                i64::try_from(min_quantity).is_ok(),
+                "rule.min_quantity",
+                "is too large",
+            )
+            .check(
+                i64::try_from(min_subtotal).is_ok(),
+                "rule.min_subtotal",
+                "is too large",
+            )
+            .check(
+                (1..=100).contains(&percent),
+                "discount.percent",
+                "must be between 1 and 100",
+            )
+            .check(amount >= 1, "discount.amount", "must be at least 1")
+            .check(
+                i64::try_from(amount).is_ok(),
+                "discount.amount",
+                "is too large",
+            )
+            .check(
+                self.max_uses.is_none_or(|max| i64::try_from(max).is_ok()),
+                "max_uses",
+                "is too large",
+            )
+            .check(
+                self.currency.is_some() || !needs_currency,
+                "currency",
+                "is required for order_total rules and amount discounts",
+            )
+            .check(
+                self.currency.as_deref().is_none_or(is_currency_code),
+                "currency",
+                "must be an ISO 4217 code such as EUR",
+            )
+            .check(
+                self.starts_at
+                    .zip(self.ends_at)
+                    .is_none_or(|(starts_at, ends_at)| starts_at < ends_at),
+                "ends_at",
+                "must be after starts_at",
+            )
+            .finish()
+    }
+}
+
+pub(crate) use service::apply_promotions;
+
+mod service {
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    };
+    use chrono::Utc;
+
+    use crate::{
+        error::{is_unique_violation, AppError, FieldError},
+        persistence::UnitOfWork,
+        validation::Valid,
+        AppState,
+    };
+
+    use super::{normalize_code, storage::PromotionDB, AppliedDiscount, OrderLine, Promotion};
+
+    fn promotion_exists(err: anyhow::Error) -> AppError {
+        if is_unique_violation(&err) {
+            AppError::Conflict {
+                code: "promotion_exists",
+                detail: "a promotion with this code already exists".to_string(),
+            }
+        } else {
+            AppError::Internal(err)
+        }
+    }
+
+    fn rejected_coupon(message: &str) -> AppError {
+        AppError::Validation(vec![FieldError {
+            field: "coupon",
+            message: message.to_string(),
+        }])
+    }
+
+    /// The discounts of the running promotions an order of `lines`, priced in
+    /// `currency`, qualifies for: those without a code, and the one `coupon`
+    /// names, counting a use of each. A coupon which is unknown, not running,
+    /// used up or for other orders is rejected.
+    pub(crate) async fn apply_promotions(
+        uow: &UnitOfWork,
+        coupon: Option<&str>,
+        lines: &[OrderLine],
+        currency: Option<&str>,
+    ) -> Result<Vec<AppliedDiscount>, AppError> {
+        let now = Utc::now();
+        let mut discounts = Vec::new();
+        for promotion in uow.promotions.list().await? {
+            if promotion.code.is_some() {
+                continue;
+            }
+            let promotion = Promotion::try_from(promotion)?;
+            if !promotion.is_running(now) {
+                continue;
+            }
+            if let Some(discount) = promotion.apply(lines, currency) {
+                if uow.promotions.use_once(promotion.id).await? {
+                    discounts.push(discount);
+                }
+            }
+        }
+        let Some(coupon) = coupon else {
+            return Ok(discounts);
+        };
+        let promotion = uow
+            .promotions
+            .find_code(&normalize_code(coupon))
+            .await?
+            .ok_or_else(|| rejected_coupon("is not a known coupon code"))?;
+        let promotion = Promotion::try_from(promotion)?;
+        if !promotion.is_running(now) {
+            return Err(rejected_coupon("has expired or is used up"));
+        }
+        let discount = promotion
+            .apply(lines, currency)
+            .ok_or_else(|| rejected_coupon("does not apply to this order"))?;
+        if !uow.promotions.use_once(promotion.id).await? {
+            return Err(rejected_coupon("has expired or is used up"));
+        }
+        discounts.push(discount);
+        Ok(discounts)
+    }
+
+    pub async fn list_promotions(state: State<AppState>) -> Result<impl IntoResponse, AppError> {
+        let promotions = state
+            .promotions
+            .list()
+            .await?
+            .into_iter()
+            .map(Promotion::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((StatusCode::OK, Json(promotions)))
+    }
+
+    pub async fn get_promotion(
+        state: State<AppState>,
+        Path(promotion_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let promotion = state
+            .promotions
+            .get(promotion_id)
+            .await?
+            .ok_or(AppError::NotFound("promotion"))?;
+        Ok((StatusCode::OK, Json(Promotion::try_from(promotion)?)))
+    }
+
+    pub async fn create_promotion(
+        state: State<AppState>,
+        Valid(promotion): Valid<Promotion>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let id = state
+            .promotions
+            .create(PromotionDB::from(promotion))
+            .await
+            .map_err(promotion_exists)?;
+        let promotion = state
+            .promotions
+            .get(id as u64)
+            .await?
+            .ok_or(AppError::NotFound("promotion"))?;
+        Ok((StatusCode::CREATED, Json(Promotion::try_from(promotion)?)))
+    }
+
+    /// Changes what the promotion gives from now on, orders keep the
+    /// discounts they got.
+    pub async fn update_promotion(
+        state: State<AppState>,
+        Path(promotion_id): Path<u64>,
+        Valid(promotion): Valid<Promotion>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let promotion = PromotionDB {
+            id: promotion_id as i64,
+            ..PromotionDB::from(promotion)
+        };
+        let updated = state
+            .promotions
+            .update(promotion)
+            .await
+            .map_err(promotion_exists)?;
+        if !updated {
+            return Err(AppError::NotFound("promotion"));
+        }
+        let promotion = state
+            .promotions
+            .get(promotion_id)
+            .await?
+            .ok_or(AppError::NotFound("promotion"))?;
+        Ok((StatusCode::OK, Json(Promotion::try_from(promotion)?)))
+    }
+
+    pub async fn delete(
+        state: State<AppState>,
+        Path(promotion_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        state.promotions.delete(promotion_id).await?;
+        Ok((StatusCode::OK, Json(())))
+    }
+}
+
+pub(crate) mod storage {
+    use std::fmt::Debug;
+
+    use anyhow::{bail, Result};
+    use axum::async_trait;
+    use chrono::{DateTime, Utc};
+    use sqlx::FromRow;
+
+    use crate::{
+        persistence::{on_conn, unique_violation, MemoryDb, Sql},
+        pet::PetCategory,
+    };
+
+    use super::{normalize_code, Discount, Promotion, PromotionRule};
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct PromotionDB {
+        pub id: i64,
+        pub code: Option<String>,
+        /// `category`, `tag` or `order_total`, see [`PromotionRule`]. The
+        /// columns of the other rules are `None`.
+        pub rule: String,
+        pub category: Option<PetCategory>,
+        pub tag: Option<String>,
+        pub min_quantity: i64,
+        pub min_subtotal: Option<i64>,
+        /// `percent`, `amount` or `free_shipping`, see [`Discount`].
+        pub discount: String,
+        pub percent: Option<i64>,
+        pub amount: Option<i64>,
+        pub currency: Option<String>,
+        pub starts_at: Option<DateTime<Utc>>,
+        pub ends_at: Option<DateTime<Utc>>,
+        pub max_uses: Option<i64>,
+        pub uses: i64,
+    }
+
+    impl From<Promotion> for PromotionDB {
+        fn from(promotion: Promotion) -> Self {
+            let mut db = PromotionDB {
+                id: promotion.id as i64,
+                code: promotion.code.as_deref().map(normalize_code),
+                rule: String::new(),
+                category: None,
+                tag: None,
+                min_quantity: 1,
+                min_subtotal: None,
+                discount: String::new(),
+                percent: None,
+                amount: None,
+                currency: promotion.currency,
+                starts_at: promotion.starts_at,
+                ends_at: promotion.ends_at,
+                max_uses: promotion.max_uses.map(|max| max as i64),
+                uses: promotion.uses as i64,
+            };
+            db.rule = match promotion.rule {
+                PromotionRule::Category {
+                    category,
+                    min_quantity,
+                } => {
+                    db.category = Some(category);
+                    db.min_quantity = min_quantity as i64;
+                    "category"
+                }
+                PromotionRule::Tag { tag, min_quantity } => {
+                    db.tag = Some(tag.trim().to_string());
+                    db.min_quantity = min_quantity as i64;
+                    "tag"
+                }
+                PromotionRule::OrderTotal { min_subtotal } => {
+                    db.min_subtotal = Some(min_subtotal as i64);
+                    "order_total"
+                }
+            }
+            .to_string();
+            db.discount = match promotion.discount {
+                Discount::Percent { percent } => {
+                    db.percent = Some(percent as i64);
+                    "percent"
+                }
+                Discount::Amount { amount } => {
+                    db.amount = Some(amount as i64);
+                    "amount"
+                }
+                Discount::FreeShipping => "free_shipping",
+            }
+            .to_string();
+            db
+        }
+    }
+
+    impl TryFrom<PromotionDB> for Promotion {
+        type Error = anyhow::Error;
+
+        fn try_from(db: PromotionDB) -> Result<Self> {
+            let min_quantity = db.min_quantity as u64;
+            let rule = match (db.rule.as_str(), db.category, db.tag, db.min_subtotal) {
+                ("category", Some(category), _, _) => PromotionRule::Category {
+                    category,
+                    min_quantity,
+                },
+                ("tag", _, Some(tag), _) => PromotionRule::Tag { tag, min_quantity },
+                ("order_total", _, _, Some(min_subtotal)) => PromotionRule::OrderTotal {
+                    min_subtotal: min_subtotal as u64,
+                },
+                (rule, ..) => bail!("promotion {} has a broken {rule:?} rule", db.id),
+            };
+            let discount = match (db.discount.as_str(), db.percent, db.amount) {
+                ("percent", Some(percent), _) => Discount::Percent {
+                    percent: percent as u64,
+                },
+                ("amount", _, Some(amount)) => Discount::Amount {
+                    amount: amount as u64,
+                },
+                ("free_shipping", _, _) => Discount::FreeShipping,
+                (discount, ..) => bail!("promotion {} has a broken {discount:?} discount", db.id),
+            };
+            Ok(Promotion {
+                id: db.id as u64,
+                code: db.code,
+                rule,
+                discount,
+                currency: db.currency,
+                starts_at: db.starts_at,
+                ends_at: db.ends_at,
+                max_uses: db.max_uses.map(|max| max as u64),
+                uses: db.uses as u64,
+            })
+        }
+    }
+
+    #[async_trait]
+    pub trait PromotionRepository: Debug + Send + Sync {
+        /// Returns the id of the new promotion, codes are unique. Starts out
+        /// unused, whatever `promotion.uses` says.
+        async fn create(&self, promotion: PromotionDB) -> Result<i64>;
+        async fn get(&self, promotion_id: u64) -> Result<Option<PromotionDB>>;
+        /// All promotions, oldest first.
+        async fn list(&self) -> Result<Vec<PromotionDB>>;
+        /// The promotion with the coupon `code`, as stored.
+        async fn find_code(&self, code: &str) -> Result<Option<PromotionDB>>;
+        /// Leaves `uses` alone. Returns `false` when there is no promotion with
+        /// `promotion.id`.
+        async fn update(&self, promotion: PromotionDB) -> Result<bool>;
+        /// Orders keep the discounts they got, without the reference.
+        async fn delete(&self, promotion_id: u64) -> Result<()>;
+        /// Counts one more use of the promotion. Returns `false` without
+        /// counting it when it is used up or gone.
+        async fn use_once(&self, promotion_id: u64) -> Result<bool>;
+        /// Gives back a use counted by [`use_once`](Self::use_once), for an
+        /// order that was cancelled.
+        async fn return_use(&self, promotion_id: u64) -> Result<()>;
+    }
+
+    #[async_trait]
+    impl PromotionRepository for Sql {
+        #[tracing::instrument(skip(self))]
+        async fn create(&self, promotion: PromotionDB) -> Result<i64> {
+            let (id,): (i64,) = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "insert into promotions (
+                        code, rule, category, tag, min_quantity, min_subtotal, discount,
+                        percent, amount, currency, starts_at, ends_at, max_uses
+                    )
+                    values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                    returning id;",
+                )
+                .bind(promotion.code)
+                .bind(promotion.rule)
+                .bind(promotion.category)
+                .bind(promotion.tag)
+                .bind(promotion.min_quantity)
+                .bind(promotion.min_subtotal)
+                .bind(promotion.discount)
+                .bind(promotion.percent)
+                .bind(promotion.amount)
+                .bind(promotion.currency)
+                .bind(promotion.starts_at)
+                .bind(promotion.ends_at)
+                .bind(promotion.max_uses)
+                .fetch_one(&mut *conn)
+                .await
+            })?;
+
+            Ok(id)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, promotion_id: u64) -> Result<Option<PromotionDB>> {
+            let res: Option<PromotionDB> = on_conn!(self, |conn| {
+                sqlx::query_as("select * from promotions p where p.id = $1")
+                    .bind(promotion_id as i64)
+                    .fetch_optional(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn list(&self) -> Result<Vec<PromotionDB>> {
+            let res: Vec<PromotionDB> = on_conn!(self, |conn| {
+                sqlx::query_as("select * from promotions p order by p.id")
+                    .fetch_all(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn find_code(&self, code: &str) -> Result<Option<PromotionDB>> {
+            let res: Option<PromotionDB> = on_conn!(self, |conn| {
+                sqlx::query_as("select * from promotions p where p.code = $1")
+                    .bind(code)
+                    .fetch_optional(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update(&self, promotion: PromotionDB) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query(
+                    "update promotions set
+                        code = $2,
+                        rule = $3,
+                        category = $4,
+                        tag = $5,
+                        min_quantity = $6,
+                        min_subtotal = $7,
+                        discount = $8,
+                        percent = $9,
+                        amount = $10,
+                        currency = $11,
+                        starts_at = $12,
+                        ends_at = $13,
+                        max_uses = $14
+                    where id = $1;",
+                )
+                .bind(promotion.id)
+                .bind(promotion.code)
+                .bind(promotion.rule)
+                .bind(promotion.category)
+                .bind(promotion.tag)
+                .bind(promotion.min_quantity)
+                .bind(promotion.min_subtotal)
+                .bind(promotion.discount)
+                .bind(promotion.percent)
+                .bind(promotion.amount)
+                .bind(promotion.currency)
+                .bind(promotion.starts_at)
+                .bind(promotion.ends_at)
+                .bind(promotion.max_uses)
+                .execute(&mut *conn)
+                .await
+                .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn delete(&self, promotion_id: u64) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query("delete from promotions where id = $1")
+                    .bind(promotion_id as i64)
+                    .execute(&mut *conn)
+                    .await?;
+            });
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn use_once(&self, promotion_id: u64) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query(
+                    "update promotions set uses = uses + 1
+                    where id = $1 and (max_uses is null or uses < max_uses);",
+                )
+                .bind(promotion_id as i64)
+                .execute(&mut *conn)
+                .await
+                .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn return_use(&self, promotion_id: u64) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query("update promotions set uses = uses - 1 where id = $1 and uses > 0;")
+                    .bind(promotion_id as i64)
+                    .execute(&mut *conn)
+                    .await
+                    .map(|_| ())
+            })?;
+
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl PromotionRepository for MemoryDb {
+        async fn create(&self, promotion: PromotionDB) -> Result<i64> {
+            let mut tables = self.lock();
+            if promotion.code.is_some()
+                && tables.promotions.values().any(|p| p.code == promotion.code)
+            {
+                return Err(unique_violation("promotions_code_idx"));
+            }
+            let id = tables.next_id();
+            tables.promotions.insert(
+                id,
+                PromotionDB {
+                    id,
+                    uses: 0,
+                    ..promotion
+                },
+            );
+            Ok(id)
+        }
+
+        async fn get(&self, promotion_id: u64) -> Result<Option<PromotionDB>> {
+            Ok(self.lock().promotions.get(&(promotion_id as i64)).cloned())
+        }
+
+        async fn list(&self) -> Result<Vec<PromotionDB>> {
+            Ok(self.lock().promotions.values().cloned().collect())
+        }
+
+        async fn find_code(&self, code: &str) -> Result<Option<PromotionDB>> {
+            Ok(self
+                .lock()
+                .promotions
+                .values()
+                .find(|p| p.code.as_deref() == Some(code))
+                .cloned())
+        }
+
+        async fn update(&self, promotion: PromotionDB) -> Result<bool> {
+            let mut tables = self.lock();
+            if promotion.code.is_some()
+                && tables
+                    .promotions
+                    .values()
+                    .any(|p| p.code == promotion.code && p.id != promotion.id)
+            {
+                return Err(unique_violation("promotions_code_idx"));
+            }
+            Ok(match tables.promotions.get_mut(&promotion.id) {
+                Some(stored) => {
+                    *stored = PromotionDB {
+                        uses: stored.uses,
+                        ..promotion
+                    };
+                    true
+                }
+                None => false,
+            })
+        }
+
+        async fn delete(&self, promotion_id: u64) -> Result<()> {
+            let mut tables = self.lock();
+            let promotion_id = promotion_id as i64;
+            tables.promotions.remove(&promotion_id);
+            for discount in tables.order_discounts.values_mut() {
+                if discount.promotion_id == Some(promotion_id) {
+                    discount.promotion_id = None;
+                }
+            }
+            Ok(())
+        }
+
+        async fn use_once(&self, promotion_id: u64) -> Result<bool> {
+            let mut tables = self.lock();
+            let promotion = tables
+                .promotions
+                .get_mut(&(promotion_id as i64))
+                .filter(|p| p.max_uses.is_none_or(|max| p.uses < max));
+            Ok(promotion.map(|p| p.uses += 1).is_some())
+        }
+
+        async fn return_use(&self, promotion_id: u64) -> Result<()> {
+            let mut tables = self.lock();
+            if let Some(promotion) = tables.promotions.get_mut(&(promotion_id as i64)) {
+                promotion.uses = (promotion.uses - 1).max(0);
+            }
+            Ok(())
+        }
+    }
+}
+
+pub mod api {
+    use axum::{middleware, routing::get, Router};
+
+    use crate::{
+        auth::{self, Permission},
+        AppState,
+    };
+
+    use super::service;
+
+    pub(crate) fn create_router() -> Router<AppState> {
+        Router::new()
+            .route(
+                "/",
+                get(service::list_promotions).post(service::create_promotion),
+            )
+            .route(
+                "/:promotion_id",
+                get(service::get_promotion)
+                    .post(service::update_promotion)
+                    .delete(service::delete),
+            )
+            .route_layer(middleware::from_fn(auth::require(
+                Permission::ManagePromotions,
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use crate::{pet::PetCategory, validation::Validate};
+
+    use super::{Discount, OrderLine, Promotion, PromotionRule};
+
+    /// 10% off all Rodents.
+    fn rodents_sale() -> Promotion {
+        Promotion {
+            id: 1,
+            code: None,
+            rule: PromotionRule::Category {
+                category: PetCategory::Rodents,
+                min_quantity: 1,
+            },
+            discount: Discount::Percent { percent: 10 },
+            currency: None,
+            starts_at: None,
+            ends_at: None,
+            max_uses: None,
+            uses: 0,
+        }
+    }
+
+    fn line(category: PetCategory, quantity: u64, amount: u64) -> OrderLine {
+        OrderLine {
+            category: Some(category),
+            tags: vec!["fluffy".to_string()],
+            quantity,
+            amount,
+        }
+    }
+
+    #[test]
+    fn apply_rules() {
+        let lines = [
+            line(PetCategory::Rodents, 2, 1995),
+            line(PetCategory::Birds, 2, 5000),
+        ];
+        let applied = rodents_sale().apply(&lines, Some("EUR")).unwrap();
+        assert_eq!(200, applied.amount, "10% of 19.95 rounded half up");
+        assert!(!applied.free_shipping);
+
+        // buy 2 birds get free shipping
+        let free_shipping = Promotion {
+            rule: PromotionRule::Category {
+                category: PetCategory::Birds,
+                min_quantity: 2,
+            },
+            discount: Discount::FreeShipping,
+            ..rodents_sale()
+        };
+        assert!(
+            free_shipping
+                .apply(&lines, Some("EUR"))
+                .unwrap()
+                .free_shipping
+        );
+        assert_eq!(None, free_shipping.apply(&lines[..1], Some("EUR")));
+
+        let fluffy = Promotion {
+            rule: PromotionRule::Tag {
+                tag: "fluffy".to_string(),
+                min_quantity: 1,
+            },
+            discount: Discount::Amount { amount: 10_000 },
+            currency: Some("EUR".to_string()),
+            ..rodents_sale()
+        };
+        assert_eq!(6995, fluffy.apply(&lines, Some("EUR")).unwrap().amount);
+        assert_eq!(None, fluffy.apply(&lines, Some("USD")));
+
+        let big_orders = Promotion {
+            rule: PromotionRule::OrderTotal { min_subtotal: 6000 },
+            discount: Discount::Percent { percent: 5 },
+            currency: Some("EUR".to_string()),
+            ..rodents_sale()
+        };
+        assert_eq!(350, big_orders.apply(&lines, Some("EUR")).unwrap().amount);
+        assert_eq!(None, big_orders.apply(&lines[1..], Some("EUR")));
+    }
+
+    #[test]
+    fn running_promotions() {
+        let now = Utc::now();
+        assert!(rodents_sale().is_running(now));
+        let ended = Promotion {
+            starts_at: Some(now - Duration::days(7)),
+            ends_at: Some(now),
+            ..rodents_sale()
+        };
+        assert!(!ended.is_running(now));
+        let used_up = Promotion {
+            max_uses: Some(3),
+            uses: 3,
+            ..rodents_sale()
+        };
+        assert!(!used_up.is_running(now));
+    }
+
+    #[test]
+    fn validate_promotion() {
+        assert!(rodents_sale().validate().is_empty());
+
+        let errors = Promotion {
+            code: Some(" ".to_string()),
+            discount: Discount::Amount { amount: 0 },
+            ends_at: Some(Utc::now() - Duration::days(1)),
+            starts_at: Some(Utc::now()),
+            ..rodents_sale()
+        }
+        .validate();
+        assert_eq!(
+            vec!["code", "discount.amount", "currency", "ends_at"],
+            errors.iter().map(|e| e.field).collect::<Vec<_>>()
+        );
+
+        let errors = Promotion {
+            rule: PromotionRule::OrderTotal {
+                min_subtotal: u64::MAX,
+            },
+            discount: Discount::Amount { amount: u64::MAX },
+            currency: Some("EUR".to_string()),
+            max_uses: Some(u64::MAX),
+            ..rodents_sale()
+        }
+        .validate();
+        assert_eq!(
+            vec!["rule.min_subtotal", "discount.amount", "max_uses"],
+            errors.iter().map(|e| e.field).collect::<Vec<_>>()
+        );
+    }
+
+    mod storage {
+        use crate::{
+            promotion::{storage::PromotionDB, Discount, Promotion, PromotionRule},
+            AppState,
+        };
+
+        #[tokio::test]
+        async fn promotion_crud() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let coupon = Promotion {
+                    code: Some(" spring10 ".to_string()),
+                    rule: PromotionRule::OrderTotal { min_subtotal: 5000 },
+                    max_uses: Some(1),
+                    currency: Some("EUR".to_string()),
+                    ..super::rodents_sale()
+                };
+                let id = state
+                    .promotions
+                    .create(PromotionDB::from(coupon.clone()))
+                    .await?;
+                let taken = state.promotions.create(PromotionDB::from(coupon)).await;
+                assert!(crate::error::is_unique_violation(&taken.unwrap_err()));
+
+                let stored = state.promotions.find_code("SPRING10").await?.unwrap();
+                assert_eq!(Some(stored.clone()), state.promotions.get(id as u64).await?);
+                assert_eq!(
+                    PromotionRule::OrderTotal { min_subtotal: 5000 },
+                    Promotion::try_from(stored.clone())?.rule
+                );
+
+                assert!(state.promotions.use_once(id as u64).await?);
+                assert!(!state.promotions.use_once(id as u64).await?, "used up");
+                state.promotions.return_use(id as u64).await?;
+                assert!(state.promotions.use_once(id as u64).await?, "returned");
+
+                let changed = PromotionDB {
+                    max_uses: Some(2),
+                    ..PromotionDB::from(Promotion {
+                        discount: Discount::FreeShipping,
+                        ..Promotion::try_from(stored)?
+                    })
+                };
+                assert!(state.promotions.update(changed).await?);
+                let updated = state.promotions.get(id as u64).await?.unwrap();
+                assert_eq!("free_shipping", updated.discount);
+                assert_eq!(1, updated.uses, "kept by updates");
+
+                state.promotions.delete(id as u64).await?;
+                assert!(state.promotions.list().await?.is_empty());
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    error::{AppError, FieldError},
+    pet::{storage::PetCount, PetStatus},
+};
+
+/// What the per status counts of the inventory can be broken down by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dimension {
+    Category,
+    Size,
+}
+
+/// Parses `breakdown=category,size`, keeping the order the caller asked for.
+fn parse_breakdown(breakdown: Option<&str>) -> Result<Vec<Dimension>, AppError> {
+    let mut dimensions = Vec::new();
+    for name in breakdown.unwrap_or_default().split(',').map(str::trim) {
+        let dimension = match name {
+            "" => continue,
+            "category" => Dimension::Category,
+            "size" => Dimension::Size,
+            _ => {
+                return Err(AppError::Validation(vec![FieldError {
+                    field: "breakdown",
+                    message: format!("unknown dimension {name:?}, expected category or size"),
+                }]))
+            }
+        };
+        if !dimensions.contains(&dimension) {
+            dimensions.push(dimension);
+        }
+    }
+    Ok(dimensions)
+}
+
+/// The serialized name of `value`, `unspecified` for pets lacking it.
+fn key<T: Serialize>(value: Option<&T>) -> String {
+    match value.map(serde_json::to_value) {
+        Some(Ok(Value::String(name))) => name,
+        _ => "unspecified".to_string(),
+    }
+}
+
+fn add(map: &mut Map<String, Value>, path: &[String], count: i64) {
+    let Some((first, rest)) = path.split_first() else {
+        return;
+    };
+    if rest.is_empty() {
+        let total = map.get(first).and_then(Value::as_i64).unwrap_or(0);
+        map.insert(first.clone(), json!(total + count));
+    } else if let Value::Object(inner) = map
+        .entry(first.clone())
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        add(inner, rest, count);
+    }
+}
+
+/// Nests the counts as status → category → size → count, leaving out the
+/// dimensions not asked for. Every status is present, even without pets.
+fn tally(counts: &[PetCount], breakdown: &[Dimension]) -> Value {
+    let mut inventory = Map::new();
+    for status in PetStatus::ALL {
+        let empty = if breakdown.is_empty() {
+            json!(0)
+        } else {
+            json!({})
+        };
+        inventory.insert(key(Some(&status)), empty);
+    }
+    for count in counts {
+        let mut path = vec![key(Some(&count.status))];
+        for dimension in breakdown {
+            path.push(match dimension {
+                Dimension::Category => key(count.category.as_ref()),
+                Dimension::Size => key(count.size.as_ref()),
+            });
+        }
+        add(&mut inventory, &path, count.count);
+    }
+    Value::Object(inventory)
+}
+
+mod service {
+    use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+    use serde::Deserialize;
+
+    use crate::{
+        error::{AppError, FieldError},
+        validation::{ValidQuery, Validate},
+        AppState,
+    };
+
+    use super::{parse_breakdown, tally};
+
+    #[derive(Deserialize)]
+    pub struct InventoryQuery {
+        breakdown: Option<String>,
+    }
+
+    impl Validate for InventoryQuery {
+        fn validate(&self) -> Vec<FieldError> {
+            match parse_breakdown(self.breakdown.as_deref()) {
+                Err(AppError::Validation(errors)) => errors,
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    /// Pet counts by status, optionally broken down by category and/or size.
+    pub async fn inventory(
+        state: State<AppState>,
+        ValidQuery(query): ValidQuery<InventoryQuery>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let breakdown = parse_breakdown(query.breakdown.as_deref())?;
+        let counts = state.pets.count().await?;
+        Ok((StatusCode::OK, Json(tally(&counts, &breakdown))))
+    }
+}
+
+pub mod api {
+    use axum::{routing::get, Router};
+
+    use crate::AppState;
+
+    use super::service;
+
+    pub(crate) fn create_router() -> Router<AppState> {
+        Router::new().route("/inventory", get(service::inventory))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::pet::{storage::PetCount, PetCategory, PetSize, PetStatus};
+
+    use super::{parse_breakdown, tally, Dimension};
+
+    fn counts() -> Vec<PetCount> {
+        vec![
+            PetCount {
+                status: PetStatus::Available,
+                category: Some(PetCategory::Feline),
+                size: Some(PetSize::House),
+                count: 2,
+            },
+            PetCount {
+                status: PetStatus::Available,
+                category: Some(PetCategory::Feline),
+                size: None,
+                count: 1,
+            },
+            PetCount {
+                status: PetStatus::Sold,
+                category: Some(PetCategory::Canine),
+                size: Some(PetSize::Flat),
+                count: 4,
+            },
+        ]
+    }
+
+    #[test]
+    fn totals_by_status() {
+        assert_eq!(
+            json!({"available": 3, "pending": 0, "sold": 4}),
+            tally(&counts(), &[])
+        );
+    }
+
+    #[test]
+    fn breakdown_by_category_and_size() {
+        assert_eq!(
+            json!({
+                "available": {"Feline": 3},
+                "pending": {},
+                "sold": {"Canine": 4},
+            }),
+            tally(&counts(), &[Dimension::Category])
+        );
+        assert_eq!(
+            json!({
+                "available": {"Feline": {"House": 2, "unspecified": 1}},
+                "pending": {},
+                "sold": {"Canine": {"Flat": 4}},
+            }),
+            tally(&counts(), &[Dimension::Category, Dimension::Size])
+        );
+        assert_eq!(
+            Some(vec![Dimension::Category, Dimension::Size]),
+            parse_breakdown(Some("category, size,category")).ok()
+        );
+        assert!(parse_breakdown(Some("colour")).is_err());
+    }
+
+    #[test]
+    fn validate_query() -> anyhow::Result<()> {
+        use crate::validation::Validate;
+
+        let query: super::service::InventoryQuery =
+            serde_json::from_value(json!({"breakdown": "size,colour"}))?;
+        let errors = query.validate();
+        assert_eq!(
+            vec!["breakdown"],
+            errors.iter().map(|e| e.field).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn inventory_from_repository() -> anyhow::Result<()> {
+        use axum::{body::to_bytes, extract::State, response::IntoResponse};
+
+        use crate::{pet::storage::PetDB, validation::ValidQuery, AppState};
+
+        for state in AppState::test_backends().await? {
+            for status in [PetStatus::Available, PetStatus::Available, PetStatus::Sold] {
+                let pet = PetDB {
+                    id: 0,
+                    category: Some(PetCategory::Rodents),
+                    status,
+                    size: Some(PetSize::Terraium),
+                    price: None,
+                    currency: None,
+                    photo_urls: Vec::new(),
+                    tags: Vec::new(),
+                    thumbnails: Vec::new(),
+                };
+                state.pets.create(pet).await?;
+            }
+
+            let query = ValidQuery(serde_json::from_value(json!({"breakdown": "size"}))?);
+            let response = super::service::inventory(State(state.clone()), query)
+                .await
+                .into_response();
+            let body = to_bytes(response.into_body(), usize::MAX).await?;
+
+            assert_eq!(
+                json!({
+                    "available": {"Terraium": 2},
+                    "pending": {},
+                    "sold": {"Terraium": 1},
+                }),
+                serde_json::from_slice::<serde_json::Value>(&body)?
+            );
+            state.shutdown().await?;
+        }
+        Ok(())
+    }
+}
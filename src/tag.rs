@@ -0,0 +1,336 @@
+use crate::{
+    error::FieldError,
+    pet::PetTag,
+    validation::{Errors, Validate},
+};
+
+impl Validate for PetTag {
+    fn validate(&self) -> Vec<FieldError> {
+        Errors::default()
+            .check(!self.name.trim().is_empty(), "name", "must not be empty")
+            .finish()
+    }
+}
+
+mod service {
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    };
+
+    use crate::{
+        error::{is_unique_violation, AppError},
+        pet::PetTag,
+        validation::Valid,
+        AppState,
+    };
+
+    use super::storage::TagDB;
+
+    fn tag_exists(err: anyhow::Error) -> AppError {
+        if is_unique_violation(&err) {
+            AppError::Conflict {
+                code: "tag_exists",
+                detail: "a tag with this name already exists".to_string(),
+            }
+        } else {
+            AppError::Internal(err)
+        }
+    }
+
+    pub async fn list_tags(state: State<AppState>) -> Result<impl IntoResponse, AppError> {
+        let tags = state.tags.list().await?;
+        Ok((
+            StatusCode::OK,
+            Json(tags.into_iter().map(PetTag::from).collect::<Vec<_>>()),
+        ))
+    }
+
+    pub async fn get_tag(
+        state: State<AppState>,
+        Path(tag_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let tag = state
+            .tags
+            .get(tag_id)
+            .await?
+            .ok_or(AppError::NotFound("tag"))?;
+        Ok((StatusCode::OK, Json(PetTag::from(tag))))
+    }
+
+    pub async fn create_tag(
+        state: State<AppState>,
+        Valid(tag): Valid<PetTag>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let name = tag.name.trim().to_string();
+        let id = state.tags.create(name.clone()).await.map_err(tag_exists)?;
+        Ok((
+            StatusCode::CREATED,
+            Json(PetTag {
+                id: id as u32,
+                name,
+            }),
+        ))
+    }
+
+    /// Renames the tag on every pet carrying it.
+    pub async fn update_tag(
+        state: State<AppState>,
+        Path(tag_id): Path<u64>,
+        Valid(tag): Valid<PetTag>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let tag = TagDB {
+            id: tag_id as i64,
+            name: tag.name.trim().to_string(),
+        };
+        if !state.tags.update(tag.clone()).await.map_err(tag_exists)? {
+            return Err(AppError::NotFound("tag"));
+        }
+        Ok((StatusCode::OK, Json(PetTag::from(tag))))
+    }
+
+    pub async fn delete(
+        state: State<AppState>,
+        Path(tag_id): Path<u64>,
+    ) -> Result<impl IntoResponse, AppError> {
+        state.tags.delete(tag_id).await?;
+        Ok((StatusCode::OK, Json(())))
+    }
+}
+
+pub(crate) mod storage {
+    use std::fmt::Debug;
+
+    use axum::async_trait;
+    use sqlx::FromRow;
+
+    use crate::{
+        persistence::{on_conn, unique_violation, MemoryDb, Sql},
+        pet::PetTag,
+    };
+    use anyhow::Result;
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct TagDB {
+        pub id: i64,
+        pub name: String,
+    }
+
+    impl From<TagDB> for PetTag {
+        fn from(tag: TagDB) -> Self {
+            PetTag {
+                id: tag.id as u32,
+                name: tag.name,
+            }
+        }
+    }
+
+    #[async_trait]
+    pub trait TagRepository: Debug + Send + Sync {
+        /// Returns the id of the new tag, names are unique.
+        async fn create(&self, name: String) -> Result<i64>;
+        async fn get(&self, tag_id: u64) -> Result<Option<TagDB>>;
+        /// All tags ordered by name.
+        async fn list(&self) -> Result<Vec<TagDB>>;
+        /// Returns `false` when there is no tag with `tag.id`.
+        async fn update(&self, tag: TagDB) -> Result<bool>;
+        /// Also takes the tag off every pet carrying it.
+        async fn delete(&self, tag_id: u64) -> Result<()>;
+    }
+
+    #[async_trait]
+    impl TagRepository for Sql {
+        #[tracing::instrument(skip(self))]
+        async fn create(&self, name: String) -> Result<i64> {
+            let (id,): (i64,) = on_conn!(self, |conn| {
+                sqlx::query_as("insert into tags (name) values ($1) returning id;")
+                    .bind(name)
+                    .fetch_one(&mut *conn)
+                    .await
+            })?;
+
+            Ok(id)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, tag_id: u64) -> Result<Option<TagDB>> {
+            let res: Option<TagDB> = on_conn!(self, |conn| {
+                sqlx::query_as("select * from tags t where t.id = $1")
+                    .bind(tag_id as i64)
+                    .fetch_optional(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn list(&self) -> Result<Vec<TagDB>> {
+            let res: Vec<TagDB> = on_conn!(self, |conn| {
+                sqlx::query_as("select * from tags t order by t.name")
+                    .fetch_all(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update(&self, tag: TagDB) -> Result<bool> {
+            let rows_affected = on_conn!(self, |conn| {
+                sqlx::query("update tags set name = $2 where id = $1;")
+                    .bind(tag.id)
+                    .bind(tag.name)
+                    .execute(&mut *conn)
+                    .await
+                    .map(|res| res.rows_affected())
+            })?;
+
+            Ok(rows_affected > 0)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn delete(&self, tag_id: u64) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query("delete from tags where id = $1")
+                    .bind(tag_id as i64)
+                    .execute(&mut *conn)
+                    .await?;
+            });
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl TagRepository for MemoryDb {
+        async fn create(&self, name: String) -> Result<i64> {
+            let mut tables = self.lock();
+            if tables.tags.values().any(|tag| tag.name == name) {
+                return Err(unique_violation("tags_name_idx"));
+            }
+            let id = tables.next_id();
+            tables.tags.insert(id, TagDB { id, name });
+            Ok(id)
+        }
+
+        async fn get(&self, tag_id: u64) -> Result<Option<TagDB>> {
+            Ok(self.lock().tags.get(&(tag_id as i64)).cloned())
+        }
+
+        async fn list(&self) -> Result<Vec<TagDB>> {
+            let mut tags: Vec<TagDB> = self.lock().tags.values().cloned().collect();
+            tags.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(tags)
+        }
+
+        async fn update(&self, tag: TagDB) -> Result<bool> {
+            let mut tables = self.lock();
+            if tables
+                .tags
+                .values()
+                .any(|other| other.name == tag.name && other.id != tag.id)
+            {
+                return Err(unique_violation("tags_name_idx"));
+            }
+            Ok(match tables.tags.get_mut(&tag.id) {
+                Some(stored) => {
+                    *stored = tag;
+                    true
+                }
+                None => false,
+            })
+        }
+
+        async fn delete(&self, tag_id: u64) -> Result<()> {
+            let mut tables = self.lock();
+            let tag_id = tag_id as i64;
+            tables.tags.remove(&tag_id);
+            tables.pet_tags.retain(|&(_, tag)| tag != tag_id);
+            Ok(())
+        }
+    }
+}
+
+pub mod api {
+    use axum::{
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+
+    use crate::{
+        auth::{self, Permission},
+        AppState,
+    };
+
+    use super::service;
+
+    pub(crate) fn create_router() -> Router<AppState> {
+        let edit_pets = middleware::from_fn(auth::require(Permission::EditPets));
+
+        Router::new()
+            .route(
+                "/",
+                get(service::list_tags)
+                    .merge(post(service::create_tag).route_layer(edit_pets.clone())),
+            )
+            .route(
+                "/:tag_id",
+                get(service::get_tag).merge(
+                    post(service::update_tag)
+                        .delete(service::delete)
+                        .route_layer(edit_pets),
+                ),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod storage {
+        use crate::{tag::storage::TagDB, AppState};
+
+        #[tokio::test]
+        async fn tag_crud() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let id = state.tags.create("hypoallergenic".to_string()).await?;
+                let fluffy = state.tags.create("fluffy".to_string()).await?;
+                let taken = state.tags.create("fluffy".to_string()).await;
+
+                assert!(crate::error::is_unique_violation(&taken.unwrap_err()));
+                assert_eq!(
+                    vec!["fluffy", "hypoallergenic"],
+                    state
+                        .tags
+                        .list()
+                        .await?
+                        .iter()
+                        .map(|tag| tag.name.as_str())
+                        .collect::<Vec<_>>()
+                );
+
+                let renamed = TagDB {
+                    id,
+                    name: "odourless".to_string(),
+                };
+                assert!(state.tags.update(renamed.clone()).await?);
+                assert_eq!(Some(renamed), state.tags.get(id as u64).await?);
+                let clash = state
+                    .tags
+                    .update(TagDB {
+                        id,
+                        name: "fluffy".to_string(),
+                    })
+                    .await;
+                assert!(crate::error::is_unique_violation(&clash.unwrap_err()));
+
+                state.tags.delete(fluffy as u64).await?;
+                assert_eq!(None, state.tags.get(fluffy as u64).await?);
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Helpers shared by the tests of several modules.
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    http::{header, Request},
+    middleware,
+    response::Response,
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    auth::{self, AuthUser, Role},
+    pet::storage::PetDB,
+    user::storage::UserDB,
+    AppState,
+};
+
+/// Inserts an account with `role`, returns its id.
+pub(crate) async fn register(state: &AppState, role: Role) -> Result<i64> {
+    let id = state
+        .users
+        .create(UserDB {
+            id: 0,
+            username: format!("{role:?}_{}", uuid::Uuid::new_v4()).to_lowercase(),
+            email: "user@example.com".to_string(),
+            password: String::new(),
+            role,
+        })
+        .await?
+        .expect("usernames are unique");
+    Ok(id)
+}
+
+/// Inserts a customer and `pet` for orders and carts to reference, returns
+/// their ids.
+pub(crate) async fn seed(state: &AppState, pet: PetDB) -> Result<(i64, i64)> {
+    let user_id = register(state, Role::Customer).await?;
+    let pet_id = state.pets.create(pet).await?;
+    Ok((user_id, pet_id))
+}
+
+/// The customer `id` as the auth middleware hands it to the handlers.
+pub(crate) fn customer(id: i64) -> AuthUser {
+    AuthUser {
+        id: id as u64,
+        username: format!("customer_{id}"),
+        role: Role::Customer,
+    }
+}
+
+/// `routes` nested under `path` behind the authentication the server puts in
+/// front of them.
+pub(crate) fn app(state: &AppState, path: &str, routes: Router<AppState>) -> Router {
+    Router::new()
+        .nest(path, routes)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::authenticate,
+        ))
+        .with_state(state.clone())
+}
+
+/// A request to `uri` carrying `body` as JSON, and a token of the account
+/// `user_id` unless it is `None`.
+pub(crate) fn request(
+    state: &AppState,
+    method: &str,
+    uri: &str,
+    user_id: Option<i64>,
+    body: Option<Value>,
+) -> Result<Request<Body>> {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(user_id) = user_id {
+        // the middleware looks the account up, the claims do not matter
+        let token = state.auth.issue(user_id as u64, "", Role::Customer)?;
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token.token));
+    }
+    let Some(body) = body else {
+        return Ok(request.body(Body::empty())?);
+    };
+    Ok(request
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))?)
+}
+
+/// Sends `request` through `router` the way the server would.
+pub(crate) async fn send(router: Router, request: Request<Body>) -> Result<Response> {
+    Ok(router.oneshot(request).await?)
+}
@@ -1,9 +1,916 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    auth::Role,
+    error::FieldError,
+    validation::{Errors, Validate},
+};
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct User {
+    #[serde(default)]
     pub id: u32,
     pub username: String,
     pub email: String,
+    /// Cleartext on the way in, never serialized back out.
+    #[serde(default, skip_serializing)]
     pub password: String,
+    /// Only admins can change it, through `POST /users/:username/role`.
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl Validate for User {
+    fn validate(&self) -> Vec<FieldError> {
+        let username = &self.username;
+        Errors::default()
+            .check(
+                (1..=64).contains(&username.chars().count()),
+                "username",
+                "must be between 1 and 64 characters long",
+            )
+            .check(
+                !username.contains(|c: char| c.is_whitespace() || c == '/'),
+                "username",
+                "must not contain whitespace or slashes",
+            )
+            // would be shadowed by `POST /users/login`
+            .check(username != "login", "username", "is reserved")
+            .check(
+                self.email.contains('@'),
+                "email",
+                "must be an email address",
+            )
+            .finish()
+    }
+}
+
+pub mod password {
+    use anyhow::{anyhow, Result};
+    use argon2::{
+        password_hash::{rand_core::OsRng, SaltString},
+        Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    };
+
+    pub enum Verification {
+        Invalid,
+        Valid,
+        /// The password matched, but the hash was produced with outdated parameters
+        /// and should be replaced with the contained one.
+        ValidRehashed(String),
+    }
+
+    fn hasher() -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+    }
+
+    fn hash_blocking(password: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = hasher()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|err| anyhow!("failed to hash password: {err}"))?;
+        Ok(hash.to_string())
+    }
+
+    fn needs_rehash(hash: &PasswordHash) -> bool {
+        let current = hasher();
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || Params::try_from(hash).map_or(true, |params| {
+                let expected = current.params();
+                params.m_cost() != expected.m_cost()
+                    || params.t_cost() != expected.t_cost()
+                    || params.p_cost() != expected.p_cost()
+            })
+    }
+
+    fn verify_blocking(hash: &str, password: &str) -> Result<Verification> {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return Ok(Verification::Invalid);
+        };
+        if hasher()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_err()
+        {
+            return Ok(Verification::Invalid);
+        }
+        if needs_rehash(&parsed) {
+            return Ok(Verification::ValidRehashed(hash_blocking(password)?));
+        }
+        Ok(Verification::Valid)
+    }
+
+    /// Hashes `password` with argon2id, returning a PHC string.
+    pub async fn hash(password: String) -> Result<String> {
+        tokio::task::spawn_blocking(move || hash_blocking(&password)).await?
+    }
+
+    /// Checks `password` against a PHC `hash`, rehashing it when the stored
+    /// parameters differ from the current ones.
+    pub async fn verify(hash: String, password: String) -> Result<Verification> {
+        tokio::task::spawn_blocking(move || verify_blocking(&hash, &password)).await?
+    }
+}
+
+mod service {
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    };
+
+    use serde::Deserialize;
+
+    use crate::{
+        auth::{AuthUser, Permission, Role},
+        error::{is_foreign_key_violation, is_unique_violation, AppError, FieldError},
+        validation::{Errors, Valid, Validate},
+        AppState,
+    };
+
+    use super::{
+        password::{self, Verification},
+        storage::UserDB,
+        User,
+    };
+
+    /// Looks up the account named `username`. Callers may manage their own
+    /// account, told apart by id as usernames can change, admins may manage
+    /// anyone's.
+    async fn find_user(
+        state: &AppState,
+        auth: &AuthUser,
+        username: &str,
+    ) -> Result<UserDB, AppError> {
+        match state.users.get_by_username(username).await? {
+            Some(user) if user.id as u64 == auth.id => Ok(user),
+            user => {
+                auth.require(Permission::ManageUsers)?;
+                user.ok_or(AppError::NotFound("user"))
+            }
+        }
+    }
+
+    fn username_taken(err: anyhow::Error) -> AppError {
+        if is_unique_violation(&err) {
+            AppError::Conflict {
+                code: "username_taken",
+                detail: "the username is already taken".to_string(),
+            }
+        } else {
+            AppError::Internal(err)
+        }
+    }
+
+    pub async fn get_user(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(username): Path<String>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = find_user(&state, &auth, &username).await?;
+        Ok((StatusCode::OK, Json::<User>(user.into())))
+    }
+
+    pub async fn list_users(state: State<AppState>) -> Result<impl IntoResponse, AppError> {
+        let res = state.users.list().await?;
+        Ok((
+            StatusCode::OK,
+            Json::<Vec<User>>(res.into_iter().map(|u| u.into()).collect()),
+        ))
+    }
+
+    #[derive(Deserialize)]
+    pub struct Credentials {
+        username: String,
+        password: String,
+    }
+
+    impl Validate for Credentials {
+        fn validate(&self) -> Vec<FieldError> {
+            Errors::default()
+                .check(!self.username.is_empty(), "username", "must not be empty")
+                .check(!self.password.is_empty(), "password", "must not be empty")
+                .finish()
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct RoleChange {
+        role: Role,
+    }
+
+    /// Any role deserialized is a valid one.
+    impl Validate for RoleChange {
+        fn validate(&self) -> Vec<FieldError> {
+            Vec::new()
+        }
+    }
+
+    /// Looks up `username` and checks `password` against the stored hash, upgrading
+    /// the hash in place when it was produced with outdated parameters.
+    pub async fn authenticate(
+        state: AppState,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<Option<User>> {
+        let Some(user) = state.users.get_by_username(username).await? else {
+            return Ok(None);
+        };
+        match password::verify(user.password.clone(), password.to_string()).await? {
+            Verification::Invalid => Ok(None),
+            Verification::Valid => Ok(Some(user.into())),
+            Verification::ValidRehashed(hash) => {
+                state.users.update_password(user.id, &hash).await?;
+                Ok(Some(user.into()))
+            }
+        }
+    }
+
+    pub async fn login(
+        state: State<AppState>,
+        Valid(credentials): Valid<Credentials>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = authenticate(
+            state.0.clone(),
+            &credentials.username,
+            &credentials.password,
+        )
+        .await?
+        .ok_or(AppError::Unauthorized("invalid username or password"))?;
+        let token = state
+            .auth
+            .issue(user.id as u64, &user.username, user.role)?;
+        Ok((StatusCode::OK, Json(token)))
+    }
+
+    pub async fn create_user(
+        state: State<AppState>,
+        Valid(mut user): Valid<User>,
+    ) -> Result<impl IntoResponse, AppError> {
+        if user.password.is_empty() {
+            return Err(AppError::Validation(vec![FieldError {
+                field: "password",
+                message: "must not be empty".to_string(),
+            }]));
+        }
+        user.role = Role::Customer;
+        user.password = password::hash(user.password).await?;
+        let id = state
+            .users
+            .create(UserDB::from(user.clone()))
+            .await?
+            .ok_or(AppError::Conflict {
+                code: "username_taken",
+                detail: "the username is already taken".to_string(),
+            })?;
+        Ok((
+            StatusCode::CREATED,
+            Json(User {
+                id: id as u32,
+                ..user
+            }),
+        ))
+    }
+
+    pub async fn update_user(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(username): Path<String>,
+        Valid(mut user): Valid<User>,
+    ) -> Result<impl IntoResponse, AppError> {
+        find_user(&state, &auth, &username).await?;
+        if !user.password.is_empty() {
+            user.password = password::hash(user.password).await?;
+        }
+        let user = state
+            .users
+            .update(&username, UserDB::from(user))
+            .await
+            .map_err(username_taken)?
+            .ok_or(AppError::NotFound("user"))?;
+        Ok((StatusCode::OK, Json::<User>(user.into())))
+    }
+
+    pub async fn set_role(
+        state: State<AppState>,
+        Path(username): Path<String>,
+        Valid(change): Valid<RoleChange>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = state
+            .users
+            .set_role(&username, change.role)
+            .await?
+            .ok_or(AppError::NotFound("user"))?;
+        Ok((StatusCode::OK, Json::<User>(user.into())))
+    }
+
+    pub async fn delete(
+        state: State<AppState>,
+        auth: AuthUser,
+        Path(username): Path<String>,
+    ) -> Result<impl IntoResponse, AppError> {
+        find_user(&state, &auth, &username).await?;
+        state.users.delete(&username).await.map_err(|err| {
+            if is_foreign_key_violation(&err) {
+                AppError::Conflict {
+                    code: "user_has_orders",
+                    detail: "the user still has orders".to_string(),
+                }
+            } else {
+                AppError::Internal(err)
+            }
+        })?;
+        Ok((StatusCode::OK, Json(())))
+    }
+}
+
+pub(crate) mod storage {
+    use std::fmt::Debug;
+
+    use axum::async_trait;
+    use sqlx::FromRow;
+
+    use crate::{
+        auth::Role,
+        persistence::{foreign_key_violation, on_conn, unique_violation, MemoryDb, Sql},
+    };
+
+    use super::User;
+    use anyhow::Result;
+
+    #[derive(Clone, FromRow, PartialEq, Debug)]
+    pub struct UserDB {
+        pub id: i64,
+        pub username: String,
+        pub email: String,
+        pub password: String,
+        pub role: Role,
+    }
+
+    impl From<User> for UserDB {
+        fn from(user: User) -> Self {
+            UserDB {
+                id: user.id as i64,
+                username: user.username,
+                email: user.email,
+                password: user.password,
+                role: user.role,
+            }
+        }
+    }
+
+    impl From<UserDB> for User {
+        fn from(user: UserDB) -> Self {
+            User {
+                id: user.id as u32,
+                username: user.username,
+                email: user.email,
+                password: user.password,
+                role: user.role,
+            }
+        }
+    }
+
+    #[async_trait]
+    pub trait UserRepository: Debug + Send + Sync {
+        /// Inserts a user ignoring `user.id`. Returns the id assigned by the
+        /// database, or `None` when the username is already taken.
+        async fn create(&self, user: UserDB) -> Result<Option<i64>>;
+        async fn get(&self, id: u64) -> Result<Option<UserDB>>;
+        async fn get_by_username(&self, username: &str) -> Result<Option<UserDB>>;
+        async fn list(&self) -> Result<Vec<UserDB>>;
+        /// Overwrites the account currently named `username`, returning the stored
+        /// row or `None` when there is no such account. An empty `user.password`
+        /// keeps the current hash and `user.role` is ignored, see
+        /// [`UserRepository::set_role`].
+        async fn update(&self, username: &str, user: UserDB) -> Result<Option<UserDB>>;
+        async fn set_role(&self, username: &str, role: Role) -> Result<Option<UserDB>>;
+        async fn update_password(&self, id: i64, hash: &str) -> Result<()>;
+        async fn delete(&self, username: &str) -> Result<()>;
+    }
+
+    #[async_trait]
+    impl UserRepository for Sql {
+        #[tracing::instrument(skip(self))]
+        async fn create(&self, user: UserDB) -> Result<Option<i64>> {
+            let res: Option<(i64,)> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "insert into users (username, email, password, role) values ($1, $2, $3, $4)
+                    on conflict (username) do nothing
+                    returning id;",
+                )
+                .bind(user.username)
+                .bind(user.email)
+                .bind(user.password)
+                .bind(user.role)
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+
+            Ok(res.map(|(id,)| id))
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get(&self, id: u64) -> Result<Option<UserDB>> {
+            let res: Option<UserDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from users u
+                    where u.id = $1",
+                )
+                .bind(id as i64)
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn get_by_username(&self, username: &str) -> Result<Option<UserDB>> {
+            let res: Option<UserDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from users u
+                    where u.username = $1",
+                )
+                .bind(username)
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn list(&self) -> Result<Vec<UserDB>> {
+            let res: Vec<UserDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "select *
+                    from users u
+                    order by u.id",
+                )
+                .fetch_all(&mut *conn)
+                .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn update(&self, username: &str, user: UserDB) -> Result<Option<UserDB>> {
+            let res: Option<UserDB> = on_conn!(self, |conn| {
+                sqlx::query_as(
+                    "update users set
+                        username = $2,
+                        email = $3,
+                        password = coalesce(nullif($4, ''), password)
+                    where username = $1
+                    returning *;",
+                )
+                .bind(username)
+                .bind(user.username)
+                .bind(user.email)
+                .bind(user.password)
+                .fetch_optional(&mut *conn)
+                .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn set_role(&self, username: &str, role: Role) -> Result<Option<UserDB>> {
+            let res: Option<UserDB> = on_conn!(self, |conn| {
+                sqlx::query_as("update users set role = $2 where username = $1 returning *;")
+                    .bind(username)
+                    .bind(role)
+                    .fetch_optional(&mut *conn)
+                    .await
+            })?;
+
+            Ok(res)
+        }
+
+        #[tracing::instrument(skip(self, hash))]
+        async fn update_password(&self, id: i64, hash: &str) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query("update users set password = $2 where id = $1")
+                    .bind(id)
+                    .bind(hash)
+                    .execute(&mut *conn)
+                    .await?;
+            });
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self))]
+        async fn delete(&self, username: &str) -> Result<()> {
+            on_conn!(self, |conn| {
+                sqlx::query("delete from users where username = $1")
+                    .bind(username)
+                    .execute(&mut *conn)
+                    .await?;
+            });
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for MemoryDb {
+        async fn create(&self, user: UserDB) -> Result<Option<i64>> {
+            let mut tables = self.lock();
+            if tables.users.values().any(|u| u.username == user.username) {
+                return Ok(None);
+            }
+            let id = tables.next_id();
+            tables.users.insert(id, UserDB { id, ..user });
+            Ok(Some(id))
+        }
+
+        async fn get(&self, id: u64) -> Result<Option<UserDB>> {
+            Ok(self.lock().users.get(&(id as i64)).cloned())
+        }
+
+        async fn get_by_username(&self, username: &str) -> Result<Option<UserDB>> {
+            Ok(self
+                .lock()
+                .users
+                .values()
+                .find(|u| u.username == username)
+                .cloned())
+        }
+
+        async fn list(&self) -> Result<Vec<UserDB>> {
+            Ok(self.lock().users.values().cloned().collect())
+        }
+
+        async fn update(&self, username: &str, user: UserDB) -> Result<Option<UserDB>> {
+            let mut tables = self.lock();
+            let Some(id) = tables
+                .users
+                .values()
+                .find(|u| u.username == username)
+                .map(|u| u.id)
+            else {
+                return Ok(None);
+            };
+            if tables
+                .users
+                .values()
+                .any(|u| u.id != id && u.username == user.username)
+            {
+                return Err(unique_violation("users_username_idx"));
+            }
+            let stored = tables.users.get_mut(&id).expect("found above");
+            stored.username = user.username;
+            stored.email = user.email;
+            if !user.password.is_empty() {
+                stored.password = user.password;
+            }
+            Ok(Some(stored.clone()))
+        }
+
+        async fn set_role(&self, username: &str, role: Role) -> Result<Option<UserDB>> {
+            let mut tables = self.lock();
+            let stored = tables.users.values_mut().find(|u| u.username == username);
+            Ok(stored.map(|u| {
+                u.role = role;
+                u.clone()
+            }))
+        }
+
+        async fn update_password(&self, id: i64, hash: &str) -> Result<()> {
+            if let Some(user) = self.lock().users.get_mut(&id) {
+                user.password = hash.to_string();
+            }
+            Ok(())
+        }
+
+        async fn delete(&self, username: &str) -> Result<()> {
+            let mut tables = self.lock();
+            let Some(id) = tables
+                .users
+                .values()
+                .find(|u| u.username == username)
+                .map(|u| u.id)
+            else {
+                return Ok(());
+            };
+            if tables.orders.values().any(|order| order.user_id == id) {
+                return Err(foreign_key_violation("orders_user_id_fkey"));
+            }
+            tables.users.remove(&id);
+            tables.carts.remove(&id);
+            tables.cart_items.retain(|_, item| item.user_id != id);
+            Ok(())
+        }
+    }
+}
+
+pub mod api {
+    use axum::{
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+
+    use crate::{
+        auth::{self, Permission},
+        AppState,
+    };
+
+    use super::service;
+
+    pub(crate) fn create_router() -> Router<AppState> {
+        let manage_users = middleware::from_fn(auth::require(Permission::ManageUsers));
+
+        Router::new()
+            .route(
+                "/",
+                post(service::create_user)
+                    .merge(get(service::list_users).route_layer(manage_users.clone())),
+            )
+            .route("/login", post(service::login))
+            .route(
+                "/:username/role",
+                post(service::set_role).route_layer(manage_users),
+            )
+            .route(
+                "/:username",
+                get(service::get_user)
+                    .delete(service::delete)
+                    .post(service::update_user),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::State;
+
+    use crate::AppState;
+
+    fn fixture() -> State<AppState> {
+        State(AppState::in_memory())
+    }
+
+    #[test]
+    fn validate_user() {
+        use crate::{user::User, validation::Validate};
+
+        let user = User {
+            username: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            ..User::default()
+        };
+        assert!(user.validate().is_empty());
+
+        for username in ["", "login", "al ice", "a/b"] {
+            let user = User {
+                username: username.to_string(),
+                ..user.clone()
+            };
+            assert_eq!(
+                vec!["username"],
+                user.validate().iter().map(|e| e.field).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    mod password {
+        use argon2::{
+            password_hash::{rand_core::OsRng, SaltString},
+            Algorithm, Argon2, Params, PasswordHasher, Version,
+        };
+
+        use crate::user::password::{self, Verification};
+
+        #[tokio::test]
+        async fn hash_and_verify() -> anyhow::Result<()> {
+            let hash = password::hash("hunter2".to_string()).await?;
+
+            assert!(hash.starts_with("$argon2id$"));
+            assert!(matches!(
+                password::verify(hash.clone(), "hunter2".to_string()).await?,
+                Verification::Valid
+            ));
+            assert!(matches!(
+                password::verify(hash, "hunter3".to_string()).await?,
+                Verification::Invalid
+            ));
+            assert!(matches!(
+                password::verify("hunter2".to_string(), "hunter2".to_string()).await?,
+                Verification::Invalid
+            ));
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn rehash_outdated_params() -> anyhow::Result<()> {
+            let weak = Argon2::new(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                Params::new(8, 1, 1, None).unwrap(),
+            );
+            let salt = SaltString::generate(&mut OsRng);
+            let old_hash = weak
+                .hash_password(b"hunter2", &salt)
+                .map_err(|err| anyhow::anyhow!("{err}"))?
+                .to_string();
+
+            let Verification::ValidRehashed(new_hash) =
+                password::verify(old_hash, "hunter2".to_string()).await?
+            else {
+                panic!("expected the hash to be upgraded");
+            };
+            assert!(matches!(
+                password::verify(new_hash, "hunter2".to_string()).await?,
+                Verification::Valid
+            ));
+            Ok(())
+        }
+    }
+
+    mod service {
+        use axum::{extract::Path, http::StatusCode, response::IntoResponse};
+        use serde_json::json;
+
+        use crate::{
+            auth::{AuthUser, Role},
+            user::{service, tests::fixture, User},
+            validation::{Valid, Validate},
+        };
+
+        #[tokio::test]
+        async fn register_and_login() -> anyhow::Result<()> {
+            let state = fixture();
+            let user = User {
+                username: "alice".to_string(),
+                email: "alice@example.com".to_string(),
+                password: "hunter2".to_string(),
+                role: Role::Admin,
+                ..User::default()
+            };
+
+            let created = service::create_user(state.clone(), Valid(user.clone())).await;
+            let duplicate = service::create_user(state.clone(), Valid(user)).await;
+
+            assert_eq!(StatusCode::CREATED, created.into_response().status());
+            assert_eq!(StatusCode::CONFLICT, duplicate.into_response().status());
+
+            let alice = service::authenticate(state.0.clone(), "alice", "hunter2").await?;
+            assert_eq!(Some(Role::Customer), alice.map(|u| u.role));
+            assert!(service::authenticate(state.0.clone(), "alice", "hunter3")
+                .await?
+                .is_none());
+            assert!(service::authenticate(state.0.clone(), "bob", "hunter2")
+                .await?
+                .is_none());
+
+            let credentials = json!({"username": "alice", "password": "hunter2"});
+            let login = service::login(state, Valid(serde_json::from_value(credentials)?)).await;
+            assert_eq!(StatusCode::OK, login.into_response().status());
+            let blank: service::Credentials =
+                serde_json::from_value(json!({"username": "alice", "password": ""}))?;
+            assert_eq!(
+                vec!["password"],
+                blank.validate().iter().map(|e| e.field).collect::<Vec<_>>()
+            );
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn access_follows_renames() -> anyhow::Result<()> {
+            let state = fixture();
+            let register = |username: &str| User {
+                username: username.to_string(),
+                email: format!("{username}@example.com"),
+                password: "hunter2".to_string(),
+                ..User::default()
+            };
+            service::create_user(state.clone(), Valid(register("alice")))
+                .await
+                .into_response();
+            let alice_id = state.users.get_by_username("alice").await?.unwrap().id;
+            // the token still says "alice"
+            let alice = || AuthUser {
+                id: alice_id as u64,
+                username: "alice".to_string(),
+                role: Role::Customer,
+            };
+
+            let renamed = service::update_user(
+                state.clone(),
+                alice(),
+                Path("alice".to_string()),
+                Valid(register("alice2")),
+            )
+            .await;
+            assert_eq!(StatusCode::OK, renamed.into_response().status());
+            service::create_user(state.clone(), Valid(register("alice")))
+                .await
+                .into_response();
+
+            let own = service::get_user(state.clone(), alice(), Path("alice2".to_string())).await;
+            let other = service::get_user(state.clone(), alice(), Path("alice".to_string())).await;
+            assert_eq!(StatusCode::OK, own.into_response().status());
+            assert_eq!(StatusCode::FORBIDDEN, other.into_response().status());
+            Ok(())
+        }
+    }
+
+    mod storage {
+        use crate::{auth::Role, user::storage::UserDB, AppState};
+
+        fn test_user(username: &str) -> UserDB {
+            UserDB {
+                id: 0,
+                username: username.to_string(),
+                email: format!("{username}@example.com"),
+                password: "secret".to_string(),
+                role: Role::Customer,
+            }
+        }
+
+        #[tokio::test]
+        async fn insert_user() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let user = test_user("insert_user_test");
+
+                let id = state.users.create(user.clone()).await?;
+                let duplicate = state.users.create(user.clone()).await?;
+                let get_res = state.users.get_by_username(&user.username).await?;
+
+                assert!(id.is_some());
+                assert_eq!(None, duplicate);
+                assert_eq!(
+                    Some(UserDB {
+                        id: id.unwrap(),
+                        ..user.clone()
+                    }),
+                    get_res
+                );
+
+                state.users.delete(&user.username).await?;
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn update_user() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let user = test_user("update_user_test");
+                let other = test_user("update_user_other_test");
+
+                state.users.create(user.clone()).await?;
+                state.users.create(other.clone()).await?;
+                let updated = state
+                    .users
+                    .update(
+                        &user.username,
+                        UserDB {
+                            email: "changed@example.com".to_string(),
+                            password: String::new(),
+                            ..user.clone()
+                        },
+                    )
+                    .await?;
+                let missing = state
+                    .users
+                    .update("no_such_user_test", user.clone())
+                    .await?;
+                let taken = state.users.update(&user.username, other.clone()).await;
+
+                let updated = updated.unwrap();
+                assert_eq!("changed@example.com", updated.email);
+                assert_eq!(user.password, updated.password);
+                assert_eq!(None, missing);
+                assert!(crate::error::is_unique_violation(&taken.unwrap_err()));
+
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn set_role() -> anyhow::Result<()> {
+            for state in AppState::test_backends().await? {
+                let user = test_user("set_role_test");
+
+                state.users.create(user.clone()).await?;
+                let promoted = state.users.set_role(&user.username, Role::Staff).await?;
+                // a regular update must not be able to change the role back
+                let updated = state.users.update(&user.username, user.clone()).await?;
+
+                assert_eq!(Some(Role::Staff), promoted.map(|u| u.role));
+                assert_eq!(Some(Role::Staff), updated.map(|u| u.role));
+
+                state.shutdown().await?;
+            }
+            Ok(())
+        }
+    }
 }
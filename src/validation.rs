@@ -0,0 +1,97 @@
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        FromRequest, FromRequestParts, Query, Request,
+    },
+    http::request::Parts,
+    Json,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::{AppError, FieldError};
+
+/// Checks a deserialized request body or query, returning one error per
+/// offending field.
+pub trait Validate {
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+/// Collects [`FieldError`]s, see [`Validate`].
+#[derive(Default)]
+pub struct Errors(Vec<FieldError>);
+
+impl Errors {
+    /// Records `message` for `field` unless `ok` holds.
+    pub fn check(&mut self, ok: bool, field: &'static str, message: &str) -> &mut Self {
+        if !ok {
+            self.0.push(FieldError {
+                field,
+                message: message.to_string(),
+            });
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<FieldError> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// Drop-in replacement for the `Json` extractor that runs [`Validate`] and
+/// rejects with a 422 problem instead of axum's plain text rejection.
+pub struct Valid<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Valid<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) =
+            Json::<T>::from_request(req, state)
+                .await
+                .map_err(|rejection: JsonRejection| {
+                    AppError::Validation(vec![FieldError {
+                        field: "body",
+                        message: rejection.body_text(),
+                    }])
+                })?;
+        let errors = value.validate();
+        if !errors.is_empty() {
+            return Err(AppError::Validation(errors));
+        }
+        Ok(Valid(value))
+    }
+}
+
+/// [`Valid`] for the query string, replacing the `Query` extractor.
+pub struct ValidQuery<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for ValidQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await.map_err(
+            |rejection: QueryRejection| {
+                AppError::Validation(vec![FieldError {
+                    field: "query",
+                    message: rejection.body_text(),
+                }])
+            },
+        )?;
+        let errors = value.validate();
+        if !errors.is_empty() {
+            return Err(AppError::Validation(errors));
+        }
+        Ok(ValidQuery(value))
+    }
+}